@@ -1,31 +1,488 @@
-use anyhow::Result;
-use clap::Parser;
-use zknet_core::{context::AppContext, network_connect, utils::get_platform_arch};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    io::{self, Write},
+    path::PathBuf,
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use futures_util::future::join_all;
+use tokio::sync::{broadcast, oneshot};
+use zknet_core::{
+    bundle::{export_network, import_network},
+    catalog::{fetch_catalog, CATALOG_MAX_AGE},
+    compat::check_client_version,
+    context::AppContext,
+    events::{Events, LifecycleEvent, NetworkEvent},
+    gc::collect_garbage,
+    lock::{lock_idle, running_session, LockInfo},
+    logparse::ClientEvent,
+    logs::tail_logs,
+    network_install,
+    readiness::Readiness,
+    registry::NetworkRegistry,
+    router::Router,
+    sessions::NetworkSessions,
+    supervisor::Shutdown,
+    updates::{check_all_updates, check_updates},
+    utils::get_platform_arch,
+};
 
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 const CONFIG_JSON: &str = include_str!("../assets/config.json");
 
+/// How long the network client may take to become ready before a warning
+const READY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Interval between checks of a session run by another process
+const ATTACH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Parser, Debug)]
-#[command(author, version, about = "ZKNetwork Client CLI")]
+#[command(
+    author,
+    version,
+    about = "ZKNetwork Client CLI",
+    args_conflicts_with_subcommands = true
+)]
 struct Cli {
     /// The ID of the network to connect to
-    network_id: String,
+    network_id: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Connect to networks, each with its own client
+    Connect {
+        #[arg(required = true)]
+        network_ids: Vec<String>,
+        /// Follow the status of the network if another process runs it
+        #[arg(long)]
+        attach: bool,
+    },
+    /// Download and verify the assets of networks without connecting
+    Install {
+        #[arg(required = true)]
+        network_ids: Vec<String>,
+    },
+    /// Check installed networks for new versions of their assets
+    Update {
+        /// Networks to check [default: all installed]
+        network_ids: Vec<String>,
+        /// Download the outdated assets
+        #[arg(long)]
+        apply: bool,
+    },
+    /// List networks available from the network catalog
+    Networks {
+        /// Fetch the catalog even if a recent copy is cached
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// List installed networks
+    List,
+    /// Show the metadata of an installed network
+    Inspect { network_id: String },
+    /// Remove an installed network and its assets
+    Remove { network_id: String },
+    /// Remove the runtime state of a network, keeping its assets
+    Reset { network_id: String },
+    /// Show the session running a network, if any
+    Status { network_id: String },
+    /// Show the latest output of a network's client
+    Logs {
+        network_id: String,
+        /// Number of lines to show
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+    },
+    /// Remove networks idle for longer than configured, stale downloads and
+    /// unreferenced blobs
+    Gc,
+    /// Pack an installed network into a bundle file for offline installs
+    Export {
+        network_id: String,
+        /// Bundle file to write [default: <network_id>.zknet.tar.gz]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Install a network from a bundle file
+    ///
+    /// Bundles are not signed: the assets are checked against the hashes in
+    /// the bundle itself, which only catches corruption. Import bundles from
+    /// trusted sources only.
+    Import { bundle: PathBuf },
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
 
     let app_name = APP_NAME.replace('_', "-");
     let platform_arch = get_platform_arch().expect("Unsupported platform or architecture");
-    println!("Starting {app_name} v{VERSION} on {platform_arch}");
+    let ctx = AppContext::new(&app_name, VERSION, CONFIG_JSON, platform_arch);
+
+    let command = match (cli.command, cli.network_id) {
+        (Some(command), _) => command,
+        (None, Some(network_id)) => Command::Connect {
+            network_ids: vec![network_id],
+            attach: false,
+        },
+        (None, None) => bail!("no network ID given; see --help"),
+    };
+
+    let registry = NetworkRegistry::new(&ctx.paths);
+    match command {
+        Command::Connect {
+            network_ids,
+            attach,
+        } => {
+            if attach {
+                let [network_id] = network_ids.as_slice() else {
+                    bail!("--attach takes a single network ID");
+                };
+                if let Some(info) = running_session(&ctx.paths, network_id)? {
+                    return follow_session(&ctx, network_id, info).await;
+                }
+            }
+
+            println!("Starting {app_name} v{VERSION} on {}", ctx.platform_arch);
+            println!("App data directory: {}", ctx.paths.dir_data().display());
+            println!("Using configuration: {:#?}", ctx.config);
+
+            return connect(ctx, &network_ids).await;
+        }
+        Command::Install { network_ids } => {
+            for network_id in network_ids {
+                let _idle = lock_idle(&ctx.paths, &network_id)?;
+                network_install(&ctx, &network_id, &Events::new(&network_id)).await?;
+                println!("Installed network {network_id}");
+            }
+        }
+        Command::Update { network_ids, apply } => {
+            let outdated = if network_ids.is_empty() {
+                check_all_updates(&ctx.paths).await?
+            } else {
+                let mut outdated = BTreeMap::new();
+                for network_id in network_ids {
+                    let updates = check_updates(&ctx.paths, &network_id).await?;
+                    if !updates.is_empty() {
+                        outdated.insert(network_id, updates);
+                    }
+                }
+                outdated
+            };
+
+            if outdated.is_empty() {
+                println!("All networks are up to date");
+            }
+            for (network_id, updates) in &outdated {
+                for u in updates {
+                    println!("{network_id}: {} is outdated", u.name);
+                }
+                if apply {
+                    let _idle = lock_idle(&ctx.paths, network_id)?;
+                    network_install(&ctx, network_id, &Events::new(network_id)).await?;
+                }
+            }
+        }
+        Command::Networks { refresh } => {
+            let max_age = if refresh {
+                Duration::ZERO
+            } else {
+                CATALOG_MAX_AGE
+            };
+            let catalog = fetch_catalog(&ctx.paths, &ctx.config.url_network, max_age).await?;
+            for n in catalog.networks {
+                println!(
+                    "{}\t{}\t[{:?}]\t{}\n\t{}",
+                    n.id,
+                    n.name,
+                    n.status,
+                    n.chains.join(", "),
+                    n.description,
+                );
+                if let Err(e) = check_client_version(&n, &ctx.client_version) {
+                    println!("\t! {e}");
+                }
+            }
+        }
+        Command::List => {
+            for n in registry.list()? {
+                println!(
+                    "{}\t{} bytes\tlast connected: {}",
+                    n.meta.id,
+                    n.size_on_disk,
+                    n.meta
+                        .last_connected
+                        .map_or("never".into(), |t| t.to_string()),
+                );
+            }
+        }
+        Command::Inspect { network_id } => {
+            let info = registry.inspect(&network_id)?;
+            println!("{}", serde_json::to_string_pretty(&info)?);
+        }
+        Command::Remove { network_id } => {
+            registry.remove(&network_id)?;
+            println!("Removed network {network_id}");
+        }
+        Command::Reset { network_id } => {
+            for path in registry.reset_state(&network_id)? {
+                println!("Removed {}", path.display());
+            }
+        }
+        Command::Status { network_id } => match running_session(&ctx.paths, &network_id)? {
+            Some(info) => println!("{}", serde_json::to_string_pretty(&info)?),
+            None => println!("Network {network_id} is not running"),
+        },
+        Command::Logs { network_id, lines } => {
+            for line in tail_logs(&ctx.paths, &network_id, lines)? {
+                println!("{line}");
+            }
+        }
+        Command::Gc => {
+            let report = collect_garbage(&registry, &ctx.config.gc, &[])?;
+            for id in &report.removed_networks {
+                println!("Removed network {id}");
+            }
+            for path in &report.removed_staging {
+                println!("Removed {}", path.display());
+            }
+            println!("Removed {} unused blob(s)", report.removed_blobs.len());
+        }
+        Command::Export { network_id, output } => {
+            let output = output.unwrap_or_else(|| format!("{network_id}.zknet.tar.gz").into());
+            export_network(&ctx.paths, &ctx.platform_arch, &network_id, &output)?;
+            println!("Exported network {network_id} to {}", output.display());
+        }
+        Command::Import { bundle } => {
+            let network_id = import_network(&ctx.paths, &ctx.platform_arch, &bundle).await?;
+            println!("Imported network {network_id}");
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Signals that end a session.
+#[derive(Debug, Clone, Copy)]
+enum Signal {
+    Interrupt,
+    Terminate,
+}
+
+impl Signal {
+    /// Exit code of a process ended by the signal, as reported by shells.
+    fn exit_code(self) -> ExitCode {
+        ExitCode::from(match self {
+            Signal::Interrupt => 128 + 2,
+            Signal::Terminate => 128 + 15,
+        })
+    }
+}
+
+async fn shutdown_signal() -> Result<Signal> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                Ok(Signal::Interrupt)
+            }
+            _ = terminate.recv() => Ok(Signal::Terminate),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await?;
+        Ok(Signal::Interrupt)
+    }
+}
+
+/// Connect to networks until interrupted by a signal, which stops their
+/// clients gracefully.
+async fn connect(ctx: AppContext, network_ids: &[String]) -> Result<ExitCode> {
+    let sessions = Arc::new(NetworkSessions::new(ctx.clone()));
+    let _router = start_router(&ctx, &sessions).await;
+    let mut all = Box::pin(join_all(
+        network_ids
+            .iter()
+            .map(|network_id| run_session(&sessions, network_id)),
+    ));
+
+    let signal = tokio::select! {
+        results = &mut all => {
+            let failed: Vec<_> = network_ids
+                .iter()
+                .zip(results)
+                .filter_map(|(id, result)| Some(format!("{id}: {:#}", result.err()?)))
+                .collect();
+            if !failed.is_empty() {
+                bail!("{}", failed.join("\n"));
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        signal = shutdown_signal() => signal?,
+    };
+    println!("Received {signal:?} signal, stopping the network clients...");
+    // cancel the sessions still starting, e.g. downloading assets or asking
+    // to reinstall, as only those already running can be stopped
+    drop(all);
+    for (network_id, result) in sessions.stop_all().await {
+        match result? {
+            Shutdown::Graceful => println!("Network client of {network_id} stopped"),
+            Shutdown::Forced => println!(
+                "Network client of {network_id} was killed after the shutdown grace period"
+            ),
+        }
+    }
+    Ok(signal.exit_code())
+}
+
+/// Start routing chain requests to the connected networks, if enabled.
+async fn start_router(ctx: &AppContext, sessions: &Arc<NetworkSessions>) -> Option<Router> {
+    let cfg = &ctx.config.router;
+    if !cfg.enabled {
+        return None;
+    }
+    match Router::start(sessions.clone(), &cfg.listen_address).await {
+        Ok(router) => {
+            println!("Routing chain requests on http://{}", router.local_addr());
+            Some(router)
+        }
+        Err(e) => {
+            eprintln!("Warning: not routing chain requests: {e:#}");
+            None
+        }
+    }
+}
+
+/// Run a session of a network until its client ends, reporting its progress.
+async fn run_session(sessions: &NetworkSessions, network_id: &str) -> Result<Shutdown> {
+    let client = sessions.client(network_id, None)?;
+    println!("Logging {network_id} to {}", client.log().path().display());
+    let _events = AbortOnDrop(tokio::spawn(report_events(client.events().subscribe())));
+    let _ready = AbortOnDrop(tokio::spawn(warn_not_ready(client.readiness().clone())));
+
+    client.start_or_reinstall(confirm_reinstall).await?;
+    client.wait().await
+}
 
-    let ctx = AppContext::new(&app_name, CONFIG_JSON, platform_arch);
-    println!("App data directory: {}", ctx.paths.dir_data().display());
-    println!("Using configuration: {:#?}", ctx.config);
+/// Follow the status of a session run by another process until it ends or a
+/// signal is received, which leaves the session running.
+async fn follow_session(ctx: &AppContext, network_id: &str, info: LockInfo) -> Result<ExitCode> {
+    println!(
+        "Network {network_id} is run by {} (pid {}); following its status",
+        info.owner, info.pid
+    );
+    let follow = async {
+        let mut last = None;
+        while let Some(info) = running_session(&ctx.paths, network_id)? {
+            let status = info.status.map(|s| (s.process.state, s.ready));
+            if let Some((state, ready)) = status.filter(|_| status != last) {
+                println!("Network client state: {state:?}, {ready:?}");
+            }
+            last = status;
+            tokio::time::sleep(ATTACH_POLL_INTERVAL).await;
+        }
+        anyhow::Ok(())
+    };
+
+    tokio::select! {
+        result = follow => {
+            result?;
+            println!("Network {network_id} stopped");
+            Ok(ExitCode::SUCCESS)
+        }
+        signal = shutdown_signal() => Ok(signal?.exit_code()),
+    }
+}
+
+/// Print the lifecycle events of the network client.
+async fn report_events(mut events: broadcast::Receiver<NetworkEvent>) {
+    loop {
+        let NetworkEvent { network_id, event } = match events.recv().await {
+            Ok(e) => e,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        match event {
+            LifecycleEvent::Resolving => println!("[{network_id}] Resolving network"),
+            LifecycleEvent::Downloading {
+                asset,
+                bytes,
+                progress,
+            } => match progress {
+                Some(percent) => println!("[{network_id}] Downloading {asset}: {percent}%"),
+                None => println!("[{network_id}] Downloading {asset}: {bytes} bytes"),
+            },
+            LifecycleEvent::Verifying => println!("[{network_id}] Verifying"),
+            LifecycleEvent::Installing => println!("[{network_id}] Installing"),
+            LifecycleEvent::Starting => println!("[{network_id}] Starting network client"),
+            LifecycleEvent::Ready => println!("[{network_id}] Network client is ready"),
+            LifecycleEvent::Degraded => println!("[{network_id}] Network client is degraded"),
+            LifecycleEvent::Exited { status } => {
+                println!("[{network_id}] Network client exited ({status})")
+            }
+            LifecycleEvent::Error { message } => eprintln!("[{network_id}] {message}"),
+            // the line itself is already on the terminal
+            LifecycleEvent::Output(line) => match line.event {
+                Some(ClientEvent::GatewayConnected) => {
+                    println!("[{network_id}] Connected to gateway")
+                }
+                Some(ClientEvent::GatewayDisconnected { reason }) => {
+                    eprintln!("[{network_id}] Lost gateway connection: {reason}")
+                }
+                Some(ClientEvent::Crashed { message }) => {
+                    eprintln!("[{network_id}] Network client crashed: {message}")
+                }
+                _ => {}
+            },
+        }
+    }
+}
+
+/// Warn if the network client does not become ready in time.
+async fn warn_not_ready(readiness: Arc<Readiness>) {
+    if let Err(e) = readiness.wait_ready(READY_TIMEOUT).await {
+        eprintln!("Warning: {e:#}");
+    }
+}
+
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
 
-    network_connect(ctx, &cli.network_id).await?;
-    Ok(())
+/// Report a tampered binary and ask whether to reinstall its network.
+fn confirm_reinstall(e: &anyhow::Error) -> impl Future<Output = Result<bool>> {
+    eprintln!("{e:#}");
+    // read on a thread of its own rather than with `spawn_blocking`, which
+    // the runtime would wait for on exit if a signal interrupts the prompt
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let answer = (|| {
+            print!("Reinstall the network and connect again? [y/N] ");
+            io::stdout().flush()?;
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer)?;
+            Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+        })();
+        let _ = tx.send(answer);
+    });
+    async { rx.await? }
 }
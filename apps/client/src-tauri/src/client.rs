@@ -0,0 +1,108 @@
+// Run the network clients through zknet_core on behalf of the frontend.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::broadcast::error::RecvError;
+use zknet_core::{
+    client::ClientStatus,
+    lock::{running_session, LockInfo},
+    paths::AppPaths,
+    router::Router,
+    sessions::NetworkSessions,
+    supervisor::Shutdown,
+};
+
+/// Payload of `network_disconnected`, emitted whenever a client ends, be it
+/// on request or not.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Disconnected {
+    network_id: String,
+    result: Result<Shutdown, String>,
+}
+
+#[tauri::command]
+pub async fn network_connect(
+    app: AppHandle,
+    sessions: State<'_, Arc<NetworkSessions>>,
+    network_id: String,
+    listen_address: Option<String>,
+) -> Result<ClientStatus, String> {
+    let listen_address = listen_address.filter(|a| !a.is_empty());
+    let client = sessions
+        .client(&network_id, listen_address.as_deref())
+        .map_err(|e| format!("{e:#}"))?;
+    if client.is_running() {
+        return Err(format!("already connected to network {network_id}"));
+    }
+
+    // forward the lifecycle events to the frontend, which also pushes them to
+    // subscribed API clients
+    let mut events = client.events().subscribe();
+    let app_events = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let _ = app_events.emit("network_event", event);
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    client.start().await.map_err(|e| format!("{e:#}"))?;
+
+    let status = client.status();
+    tauri::async_runtime::spawn(async move {
+        let result = client.wait().await.map_err(|e| format!("{e:#}"));
+        let _ = app.emit("network_disconnected", Disconnected { network_id, result });
+    });
+    Ok(status)
+}
+
+#[tauri::command]
+pub async fn network_disconnect(
+    sessions: State<'_, Arc<NetworkSessions>>,
+    network_id: String,
+) -> Result<Shutdown, String> {
+    sessions
+        .stop(&network_id)
+        .await
+        .map_err(|e| format!("{e:#}"))
+}
+
+#[tauri::command]
+pub fn network_status(
+    sessions: State<'_, Arc<NetworkSessions>>,
+    network_id: &str,
+) -> Option<ClientStatus> {
+    sessions.get(network_id).map(|c| c.status())
+}
+
+/// Status of every network connected since the app started.
+#[tauri::command]
+pub fn networks_status(sessions: State<'_, Arc<NetworkSessions>>) -> Vec<ClientStatus> {
+    sessions.status()
+}
+
+/// Address of the router forwarding chain requests to the connected networks,
+/// if it is running.
+#[tauri::command]
+pub fn router_address(app: AppHandle) -> Option<String> {
+    app.try_state::<Router>()
+        .map(|router| router.local_addr().to_string())
+}
+
+/// The session running a network, which may belong to another process such as
+/// the CLI.
+#[tauri::command]
+pub fn network_session(
+    paths: State<'_, AppPaths>,
+    network_id: &str,
+) -> Result<Option<LockInfo>, String> {
+    running_session(&paths, network_id).map_err(|e| e.to_string())
+}
@@ -8,6 +8,7 @@ use tauri::{AppHandle, Runtime};
 pub struct ZKNetClientCfg {
     pub api_listen_address: String,
     pub default_walletshield_listen_address: String,
+    pub update_check_interval_secs: u64,
     pub url_network: String,
 }
 
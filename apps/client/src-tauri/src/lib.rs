@@ -1,14 +1,16 @@
-use tauri::Manager;
+use std::{sync::Arc, time::Duration};
 
+use tauri::{Emitter, Manager};
+use zknet_core::{
+    context::AppContext, paths::AppPaths, registry::NetworkRegistry, router::Router,
+    sessions::NetworkSessions, updates::watch_updates, utils::get_platform_arch,
+};
+
+mod client;
 mod config;
+mod networks;
 mod ws_server;
 
-// Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-#[tauri::command]
-fn network_connect(network_id: &str) -> String {
-    format!("Network Id set to: {}", network_id)
-}
-
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -73,14 +75,73 @@ pub fn run() {
             let cfg = config::plugin_cfg::<_, config::ZKNetClientCfg>(&app.handle(), "zknet");
             app.manage(cfg);
 
+            // share the app's local data dir with zknet_core, and session locks
+            // with the CLI
+            let paths = AppPaths::from_dir(app.path().app_local_data_dir()?).with_shared_locks();
+            app.manage(NetworkRegistry::new(&paths));
+
+            // run network clients with the app's configuration, where the
+            // configured listen address is the default
+            let cfg = app.state::<config::ZKNetClientCfg>();
+            let config_json = serde_json::json!({
+                "apiListenAddress": cfg.api_listen_address,
+                "urlNetwork": cfg.url_network,
+                "walletshieldListenAddress": cfg.default_walletshield_listen_address,
+                "updates": { "checkIntervalSecs": cfg.update_check_interval_secs },
+            });
+            let sessions = Arc::new(NetworkSessions::new(AppContext::from_paths(
+                paths.clone(),
+                &app.package_info().version.to_string(),
+                &config_json.to_string(),
+                get_platform_arch()?,
+            )));
+            app.manage(sessions.clone());
+
+            // periodically check installed networks for new assets
+            let interval = sessions.context().config.updates.check_interval_secs;
+            if interval > 0 {
+                let app_handle = app.handle().clone();
+                let interval = Duration::from_secs(interval);
+                tauri::async_runtime::spawn(watch_updates(paths.clone(), interval, move |o| {
+                    let _ = app_handle.emit("network_updates", o);
+                }));
+            }
+            app.manage(paths);
+
+            // route chain requests to the connected networks on one address
+            let cfg = sessions.context().config.router.clone();
+            if cfg.enabled {
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    match Router::start(sessions, &cfg.listen_address).await {
+                        Ok(router) => {
+                            app_handle.manage(router);
+                        }
+                        Err(e) => eprintln!("Not routing chain requests: {e:#}"),
+                    }
+                });
+            }
+
             // start a WebSocket server for local API requests
             let addr = &app.state::<config::ZKNetClientCfg>().api_listen_address;
             ws_server::start(&app.handle(), addr);
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
-            network_connect,
+            client::network_connect,
+            client::network_disconnect,
+            client::network_session,
+            client::network_status,
+            client::networks_status,
+            client::router_address,
             config::cfg,
+            networks::network_check_updates,
+            networks::network_inspect,
+            networks::network_logs,
+            networks::network_remove,
+            networks::network_reset,
+            networks::networks_catalog,
+            networks::networks_list,
             ws_server::api_reply,
         ])
         .run(tauri::generate_context!())
@@ -0,0 +1,81 @@
+// Expose the zknet_core network registry and catalog to the frontend.
+
+use std::time::Duration;
+
+use tauri::State;
+use zknet_core::{
+    catalog::{fetch_catalog, Catalog, CATALOG_MAX_AGE},
+    logs::tail_logs,
+    paths::AppPaths,
+    registry::{NetworkInfo, NetworkRegistry},
+    updates::{check_updates, AssetUpdate},
+};
+
+use crate::config::ZKNetClientCfg;
+
+#[tauri::command]
+pub async fn networks_catalog(
+    paths: State<'_, AppPaths>,
+    cfg: State<'_, ZKNetClientCfg>,
+    refresh: bool,
+) -> Result<Catalog, String> {
+    let max_age = if refresh {
+        Duration::ZERO
+    } else {
+        CATALOG_MAX_AGE
+    };
+    fetch_catalog(&paths, &cfg.url_network, max_age)
+        .await
+        .map_err(|e| format!("{e:#}"))
+}
+
+#[tauri::command]
+pub fn networks_list(registry: State<'_, NetworkRegistry>) -> Result<Vec<NetworkInfo>, String> {
+    registry.list().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn network_inspect(
+    registry: State<'_, NetworkRegistry>,
+    network_id: &str,
+) -> Result<NetworkInfo, String> {
+    registry.inspect(network_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn network_remove(
+    registry: State<'_, NetworkRegistry>,
+    network_id: &str,
+) -> Result<(), String> {
+    registry.remove(network_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn network_reset(
+    registry: State<'_, NetworkRegistry>,
+    network_id: &str,
+) -> Result<Vec<String>, String> {
+    let removed = registry
+        .reset_state(network_id)
+        .map_err(|e| e.to_string())?;
+    Ok(removed.iter().map(|p| p.display().to_string()).collect())
+}
+
+#[tauri::command]
+pub async fn network_check_updates(
+    paths: State<'_, AppPaths>,
+    network_id: &str,
+) -> Result<Vec<AssetUpdate>, String> {
+    check_updates(&paths, network_id)
+        .await
+        .map_err(|e| format!("{e:#}"))
+}
+
+#[tauri::command]
+pub fn network_logs(
+    paths: State<'_, AppPaths>,
+    network_id: &str,
+    lines: usize,
+) -> Result<Vec<String>, String> {
+    tail_logs(&paths, network_id, lines).map_err(|e| e.to_string())
+}
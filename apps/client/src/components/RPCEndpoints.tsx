@@ -4,6 +4,8 @@ import { writeText } from '@tauri-apps/plugin-clipboard-manager';
 import * as log from '@tauri-apps/plugin-log';
 import {
   NetworkServices,
+  getNetworkStatus,
+  getRouterAddress,
   getWalletshieldListenAddress,
   readNetworkAssetFile,
 } from '../utils';
@@ -12,44 +14,53 @@ import { IconClipboard } from './icons';
 export function RPCEndpoints() {
   const [includeTestnets, setIncludeTestnets] = useState(true);
   const [search, setSearch] = useState('');
-  const [services, setServices] = useState<NetworkServices | null>();
+  const [services, setServices] = useState<
+    { networkId: string; baseUrl: string; services: NetworkServices }[]
+  >([]);
   const [copied, setCopied] = useState('');
-  const [walletshieldListenAddr, setWalletshieldListenAddr] = useState('');
 
-  const networkConnected = useStore((s) => s.networkConnected);
+  const networksConnected = useStore((s) => s.networksConnected);
   const setMessage = useStore((s) => s.setMessage);
 
-  const BASE_URL = `http://localhost${walletshieldListenAddr}`;
-
   useEffect(() => {
-    try {
-      (async () => {
-        setWalletshieldListenAddr(await getWalletshieldListenAddress());
-
-        // read services file of the connected network
-        if (networkConnected) {
-          const f = await readNetworkAssetFile(
-            networkConnected,
-            'services.json',
-          );
-          const s = JSON.parse(f) as NetworkServices;
-          setServices(s);
-        }
-      })();
-    } catch (error: any) {
+    (async () => {
+      // read the services file of each connected network, whose client may
+      // listen on another port than the configured one; the router serves
+      // all of them on a single address
+      const router = await getRouterAddress();
+      const all = await Promise.all(
+        networksConnected.map(async (networkId) => {
+          const status = await getNetworkStatus(networkId);
+          const addr =
+            status?.listenAddress ?? (await getWalletshieldListenAddress());
+          const f = await readNetworkAssetFile(networkId, 'services.json');
+          return {
+            networkId,
+            baseUrl: router ? `http://${router}` : `http://localhost${addr}`,
+            services: JSON.parse(f) as NetworkServices,
+          };
+        }),
+      );
+      setServices(all);
+    })().catch((error: any) => {
       log.error(`${error}`);
       setMessage('error', `${error}`);
-    }
-  }, []);
+    });
+  }, [networksConnected]);
 
-  const handleCopy = async (rpcPath: string) => {
-    await writeText(`${BASE_URL}${rpcPath}`);
-    setCopied(rpcPath);
+  const handleCopy = async (url: string) => {
+    await writeText(url);
+    setCopied(url);
   };
 
   const filtered = useMemo(() => {
-    if (!services) return [];
-    const endpoints = services.RPCEndpoints;
+    const endpoints = services.flatMap(({ networkId, baseUrl, services }) =>
+      services.RPCEndpoints.map((e) => ({
+        ...e,
+        networkId,
+        url: `${baseUrl}${e.rpcPath}`,
+      })),
+    );
     return endpoints
       .filter((n) => includeTestnets || !n.isTestnet)
       .filter((n) => {
@@ -61,7 +72,7 @@ export function RPCEndpoints() {
       });
   }, [includeTestnets, search, services]);
 
-  if (!networkConnected) {
+  if (networksConnected.length === 0) {
     return (
       <div className="p-4 flex justify-center">
         <div className="alert alert-warning shadow-lg">
@@ -101,6 +112,7 @@ export function RPCEndpoints() {
         <table className="table w-full table-zebra">
           <thead>
             <tr>
+              <th>ZKN Network</th>
               <th>Chain</th>
               <th>Network</th>
               <th>ChainID</th>
@@ -110,18 +122,19 @@ export function RPCEndpoints() {
           <tbody>
             {filtered.map((n, idx) => (
               <tr key={idx}>
+                <td>{n.networkId}</td>
                 <td>{n.chain}</td>
                 <td>{n.network}</td>
                 <td>{n.chainId ?? ''}</td>
                 <td
                   className="flex items-center gap-x-1 hover:cursor-pointer"
-                  onClick={() => handleCopy(n.rpcPath)}
+                  onClick={() => handleCopy(n.url)}
                 >
                   <IconClipboard
                     className="size-5"
-                    withCheck={copied === n.rpcPath}
+                    withCheck={copied === n.url}
                   />
-                  {`${BASE_URL}${n.rpcPath}`}
+                  {n.url}
                 </td>
               </tr>
             ))}
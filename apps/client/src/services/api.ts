@@ -1,10 +1,18 @@
 import { listen } from '@tauri-apps/api/event';
 import { invoke } from '@tauri-apps/api/core';
-import { getClientStatus } from '../utils';
+import {
+  getClientStatus,
+  getNetworksStatus,
+  getNetworkStatus,
+  NetworkEvent,
+} from '../utils';
 
 // connected API clients
 const clients = new Set<number>();
 
+// API clients subscribed to network events
+const subscribers = new Set<number>();
+
 // Notify all connected API clients of a method call with parameters.
 export const notifyAPIClients = async (method: string, params: any) => {
   const data = JSON.stringify({ jsonrpc: '2.0', method, params });
@@ -26,17 +34,30 @@ listen<number>('api_conn_open', (e) => {
 
 listen<number>('api_conn_close', (e) => {
   clients.delete(e.payload);
+  subscribers.delete(e.payload);
   console.log('API client left', e.payload);
 });
 
 listen<{ conn_id: number; data: string }>(
   'api_request',
   async ({ payload }) => {
-    const reply = await handle(payload.data);
+    const reply = await handle(payload.conn_id, payload.data);
     await invoke('api_reply', { connId: payload.conn_id, data: reply });
   },
 );
 
+// Push the network client's lifecycle events to subscribed API clients.
+listen<NetworkEvent>('network_event', (e) => {
+  const data = JSON.stringify({
+    jsonrpc: '2.0',
+    method: 'networkEvent',
+    params: e.payload,
+  });
+  subscribers.forEach(async (connId) => {
+    await invoke('api_reply', { connId, data });
+  });
+});
+
 export const enum RpcCode {
   PARSE = -32700,
   INVALID_REQUEST = -32600,
@@ -67,7 +88,7 @@ const err = (id: number | string | null, e: RpcError) =>
     },
   });
 
-async function handle(raw: string): Promise<string | void> {
+async function handle(connId: number, raw: string): Promise<string | void> {
   let req: any;
 
   try {
@@ -86,7 +107,7 @@ async function handle(raw: string): Promise<string | void> {
   const isNotification = req.id === undefined;
 
   try {
-    const result = await route(req.method, req.params);
+    const result = await route(connId, req.method, req.params);
     if (isNotification) return; // no response ⇒ notification
     return JSON.stringify({ jsonrpc: '2.0', id: req.id, result });
   } catch (e: any) {
@@ -99,11 +120,35 @@ async function handle(raw: string): Promise<string | void> {
   }
 }
 
-async function route(method: string, params: any) {
+async function route(connId: number, method: string, params: any) {
   switch (method) {
     case 'getStatus':
       return await getClientStatus();
 
+    case 'getNetworkStatus': {
+      if (typeof params?.networkId !== 'string') {
+        throw new RpcError(RpcCode.INVALID_PARAMS, 'networkId is required');
+      }
+      const status = await getNetworkStatus(params.networkId);
+      if (!status) {
+        throw new RpcError(
+          RpcCode.INVALID_PARAMS,
+          `Network ${params.networkId} is not connected`,
+        );
+      }
+      return status;
+    }
+
+    case 'listNetworks':
+      return await getNetworksStatus();
+
+    case 'subscribe':
+      subscribers.add(connId);
+      return true;
+
+    case 'unsubscribe':
+      return subscribers.delete(connId);
+
     case 'echo':
       return params;
 
@@ -19,15 +19,13 @@ export const useStore = create(
   combine(
     {
       appVersion: '',
-      clientPid: 0,
       consoleLines: [] as string[],
       consoleLinesLimit: 100,
-      isConnected: false,
-      isStopping: false,
       isPlatformSupported: false,
       message: '',
       messageType: '',
-      networkConnected: '',
+      networksConnected: [] as string[],
+      networksStopping: [] as string[],
       networks: [] as string[],
       platformArch: '',
       updateStatus: '' as UpdateStatus,
@@ -41,17 +39,24 @@ export const useStore = create(
         }),
 
       setAppVersion: (appVersion: string) => set({ appVersion }),
-      setClientPid: (clientPid: number) => set({ clientPid }),
-      setIsConnected: (isConnected: boolean) => set({ isConnected }),
-      setIsStopping: (isStopping: boolean) => set({ isStopping }),
       setIsPlatformSupported: (isPlatformSupported: boolean) =>
         set({ isPlatformSupported }),
       setMessage: (
         messageType: 'error' | 'info' | 'success',
         message: string,
       ) => set({ message, messageType }),
-      setNetworkConnected: (networkConnected: string) =>
-        set({ networkConnected }),
+      setNetworkConnected: (networkId: string, connected: boolean) =>
+        set(({ networksConnected }) => ({
+          networksConnected: connected
+            ? [...networksConnected.filter((n) => n !== networkId), networkId]
+            : networksConnected.filter((n) => n !== networkId),
+        })),
+      setNetworkStopping: (networkId: string, stopping: boolean) =>
+        set(({ networksStopping }) => ({
+          networksStopping: stopping
+            ? [...networksStopping.filter((n) => n !== networkId), networkId]
+            : networksStopping.filter((n) => n !== networkId),
+        })),
       setNetworks: (networks: string[]) => set({ networks }),
       setPlatformArch: (platformArch: string) => set({ platformArch }),
       setUpdateStatus: (updateStatus: UpdateStatus) => set({ updateStatus }),
@@ -1,4 +1,4 @@
-import { BaseDirectory, readDir, readTextFile } from '@tauri-apps/plugin-fs';
+import { BaseDirectory, readTextFile } from '@tauri-apps/plugin-fs';
 import { path } from '@tauri-apps/api';
 import { arch, platform } from '@tauri-apps/plugin-os';
 import { invoke } from '@tauri-apps/api/core';
@@ -8,6 +8,7 @@ import { useStore } from '../store';
 export interface ZKNetClientCfg {
   apiListenAddress: string;
   defaultWalletshieldListenAddress: string;
+  updateCheckIntervalSecs: number;
   urlNetwork: string;
 }
 
@@ -41,12 +42,91 @@ export const getPlatformArch = (): string => {
   }
 };
 
+// Metadata of an installed network, as recorded by zknet_core
+export interface NetworkInfo {
+  id: string;
+  sourceUrl: string | null;
+  installedAt: number | null;
+  lastConnected: number | null;
+  assets: { name: string; sha256: string; size: number }[];
+  sizeOnDisk: number;
+}
+
+// A network published in the catalog at urlNetwork
+export interface CatalogEntry {
+  id: string;
+  name: string;
+  description: string;
+  chains: string[];
+  status: 'active' | 'maintenance' | 'deprecated' | 'unknown';
+  minClientVersion: string | null;
+  maxClientVersion: string | null;
+}
+
+// Get the networks available from the network catalog
+export const getCatalog = async (refresh = false) => {
+  const catalog = await invoke<{ networks: CatalogEntry[] }>(
+    'networks_catalog',
+    { refresh },
+  );
+  return catalog.networks;
+};
+
+// Get the latest lines logged by a network's client
+export const getNetworkLogs = async (networkId: string, lines = 200) =>
+  invoke<string[]>('network_logs', { networkId, lines });
+
+// Status of a network's client
+export type ClientStatus = {
+  networkId: string;
+  running: boolean;
+  ready: 'starting' | 'connectingToGateway' | 'ready' | 'degraded';
+  listenAddress: string | null;
+  process: {
+    state: 'starting' | 'running' | 'restarting' | 'stopped' | 'failed';
+    pid: number | null;
+    restarts: number;
+    lastExit: string | null;
+  };
+  error: string | null;
+  // protections applied by the last walletshield spawned, if any
+  sandbox: Record<
+    'filesystem' | 'syscalls' | 'capabilities',
+    'active' | 'unsupported' | 'disabled'
+  > | null;
+};
+
+// Get the status of a network's client, if it was connected before
+export const getNetworkStatus = async (networkId: string) =>
+  invoke<ClientStatus | null>('network_status', { networkId });
+
+// Get the status of every network connected since the app started
+export const getNetworksStatus = async () =>
+  invoke<ClientStatus[]>('networks_status');
+
+// Get the address of the router forwarding chain requests to the connected
+// networks, if it is running
+export const getRouterAddress = async () =>
+  invoke<string | null>('router_address');
+
+// Session running a network, possibly in another process such as the CLI
+export type NetworkSession = {
+  pid: number;
+  owner: string;
+  networkId: string;
+  listenAddress: string;
+  startedAt: number;
+  status: ClientStatus | null;
+};
+
+// Get the session running a network, if any
+export const getNetworkSession = async (networkId: string) =>
+  invoke<NetworkSession | null>('network_session', { networkId });
+
 // Get networks with previously downloaded assets
 export const getNetworks = async () => {
-  const entries = await readDir('networks', {
-    baseDir: BaseDirectory.AppLocalData,
-  });
-  return entries.filter((i) => i.isDirectory).map((i) => i.name);
+  const networks = await invoke<NetworkInfo[]>('networks_list');
+  return networks.map((n) => n.id);
 };
 
 export const readNetworkAssetFile = async (
@@ -67,12 +147,47 @@ export type NetworkServices = {
   }[];
 };
 
+// Lifecycle event of a network's client, as emitted by the backend
+export type NetworkEvent = { networkId: string } & (
+  | { kind: 'resolving' }
+  | {
+      kind: 'downloading';
+      asset: string;
+      bytes: number;
+      progress: number | null;
+    }
+  | { kind: 'verifying' }
+  | { kind: 'installing' }
+  | { kind: 'starting' }
+  | { kind: 'ready' }
+  | { kind: 'degraded' }
+  | { kind: 'exited'; status: string }
+  | { kind: 'error'; message: string }
+  | {
+      kind: 'output';
+      timestamp: string;
+      stream: 'stdout' | 'stderr';
+      line: string;
+      level: 'debug' | 'info' | 'notice' | 'warning' | 'error' | 'critical';
+      // event reported by the line, e.g. { kind: 'gatewayConnected' }
+      event: { kind: string } | null;
+    }
+);
+
 export type ZKNetClientStatus = {
   app: {
     version: string;
   };
   network: {
     isConnected: boolean;
+    networks: {
+      networkId: string;
+      listenAddress: string | null;
+      ready: ClientStatus['ready'];
+    }[];
+  };
+  router: {
+    listenAddress: string | null;
   };
   settings: {
     walletshield: {
@@ -83,18 +198,33 @@ export type ZKNetClientStatus = {
 
 export const getClientStatus = async (): Promise<ZKNetClientStatus> => {
   const s = useStore.getState();
-  const wla = await getWalletshieldListenAddress();
+  // a running client may listen on another port than the configured one
+  const networks = (await getNetworksStatus())
+    .filter((c) => c.running)
+    .map(({ networkId, listenAddress, ready }) => ({
+      networkId,
+      listenAddress,
+      ready,
+    }));
 
   return {
     app: {
       version: s.appVersion,
     },
     network: {
-      isConnected: s.isConnected,
+      isConnected: networks.length > 0,
+      networks,
+    },
+    router: {
+      listenAddress: await getRouterAddress(),
     },
     settings: {
       walletshield: {
-        listenAddress: wla,
+        // kept for extensions that only know a single network: the address
+        // of the first running client, else the configured one
+        listenAddress:
+          networks.find((n) => n.listenAddress)?.listenAddress ??
+          (await getWalletshieldListenAddress()),
       },
     },
   };
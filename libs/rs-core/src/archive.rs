@@ -0,0 +1,309 @@
+//! Safe extraction of archived assets (`.tar.gz` and `.zip`).
+//!
+//! Entries must stay within the destination directory: absolute paths and
+//! `..` components are rejected, and symlink targets may only point downwards
+//! (relative, without `..`) so that no chain of links can escape. Special
+//! files and hard links are refused, and permission bits are honored without
+//! setuid/setgid/sticky bits.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use flate2::read::GzDecoder;
+use tar::EntryType;
+
+/// Upper bound on the bytes written by a single extraction.
+const MAX_EXTRACTED_BYTES: u64 = 1 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    TarGz,
+    Zip,
+}
+
+/// Detect whether the file at `path` is a supported archive by its magic bytes.
+pub fn sniff(path: &Path) -> Result<Option<ArchiveKind>> {
+    let mut magic = Vec::with_capacity(4);
+    File::open(path)?.take(4).read_to_end(&mut magic)?;
+    Ok(match magic.as_slice() {
+        [0x1f, 0x8b, ..] => Some(ArchiveKind::TarGz),
+        [b'P', b'K', 0x03, 0x04] => Some(ArchiveKind::Zip),
+        _ => None,
+    })
+}
+
+/// Extract `archive` into `dest`, returning the relative paths of the
+/// extracted files and symlinks.
+pub fn extract(archive: &Path, kind: ArchiveKind, dest: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dest)?;
+    let mut x = Extractor {
+        dest,
+        files: Vec::new(),
+        written: 0,
+    };
+    match kind {
+        ArchiveKind::TarGz => x.tar_gz(archive)?,
+        ArchiveKind::Zip => x.zip(archive)?,
+    }
+    x.files.sort();
+    x.files.dedup();
+    Ok(x.files)
+}
+
+struct Extractor<'a> {
+    dest: &'a Path,
+    files: Vec<PathBuf>,
+    written: u64,
+}
+
+impl Extractor<'_> {
+    fn tar_gz(&mut self, archive: &Path) -> Result<()> {
+        let mut ar = tar::Archive::new(GzDecoder::new(File::open(archive)?));
+        for entry in ar.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            match entry.header().entry_type() {
+                EntryType::Directory => self.dir(&path)?,
+                EntryType::Regular | EntryType::Continuous => {
+                    let mode = entry.header().mode()?;
+                    self.file(&path, &mut entry, mode)?
+                }
+                EntryType::Symlink => {
+                    let target = entry
+                        .link_name()?
+                        .with_context(|| format!("symlink {} has no target", path.display()))?
+                        .into_owned();
+                    self.symlink(&path, &target)?
+                }
+                // metadata entries are applied to the following entry by `tar`
+                EntryType::XGlobalHeader | EntryType::XHeader => {}
+                t => bail!("unsupported archive entry {} ({t:?})", path.display()),
+            }
+        }
+        Ok(())
+    }
+
+    fn zip(&mut self, archive: &Path) -> Result<()> {
+        let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i)?;
+            let path = PathBuf::from(entry.name());
+            if entry.is_dir() {
+                self.dir(&path)?;
+            } else if entry.is_symlink() {
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                self.symlink(&path, Path::new(&target))?;
+            } else {
+                let mode = entry.unix_mode().unwrap_or(0o644);
+                self.file(&path, &mut entry, mode)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Map an entry path to its location below `dest`.
+    fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        let rel = normal_path(path)
+            .with_context(|| format!("unsafe path in archive: {}", path.display()))?;
+        Ok(self.dest.join(rel))
+    }
+
+    fn dir(&mut self, path: &Path) -> Result<()> {
+        fs::create_dir_all(self.resolve(path)?)?;
+        Ok(())
+    }
+
+    fn file(&mut self, path: &Path, reader: &mut impl Read, mode: u32) -> Result<()> {
+        let out = self.resolve(path)?;
+        self.prepare(&out)?;
+
+        let remaining = MAX_EXTRACTED_BYTES - self.written;
+        let mut file = File::create(&out)?;
+        let n = io::copy(&mut reader.take(remaining + 1), &mut file)?;
+        ensure!(
+            n <= remaining,
+            "archive expands to more than {MAX_EXTRACTED_BYTES} bytes"
+        );
+        self.written += n;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // drop setuid/setgid/sticky, keep the owner able to replace it
+            let mode = (mode & 0o777) | 0o600;
+            fs::set_permissions(&out, fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+
+        self.files.push(out.strip_prefix(self.dest)?.to_path_buf());
+        Ok(())
+    }
+
+    fn symlink(&mut self, path: &Path, target: &Path) -> Result<()> {
+        ensure!(
+            normal_path(target).is_some(),
+            "symlink {} points outside its directory: {}",
+            path.display(),
+            target.display()
+        );
+        let out = self.resolve(path)?;
+        self.prepare(&out)?;
+        make_symlink(target, &out)?;
+
+        self.files.push(out.strip_prefix(self.dest)?.to_path_buf());
+        Ok(())
+    }
+
+    /// Create the parent directories of `out` and clear a previous entry.
+    fn prepare(&self, out: &Path) -> Result<()> {
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match out.symlink_metadata() {
+            Ok(m) if m.is_dir() => bail!("archive entry replaces directory {}", out.display()),
+            Ok(_) => fs::remove_file(out)?,
+            Err(_) => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn make_symlink(target: &Path, out: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, out)
+}
+
+#[cfg(not(unix))]
+fn make_symlink(_target: &Path, _out: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks in archives are not supported on this platform",
+    ))
+}
+
+/// Strip `.` components, returning `None` unless only plain names remain.
+pub(crate) fn normal_path(path: &Path) -> Option<PathBuf> {
+    let mut rel = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(p) => rel.push(p),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!rel.as_os_str().is_empty()).then_some(rel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    fn tar_gz(path: &Path, entries: &[(&str, EntryType, &[u8], u32)]) {
+        let gz = GzEncoder::new(File::create(path).unwrap(), Compression::fast());
+        let mut builder = tar::Builder::new(gz);
+        for (name, kind, data, mode) in entries {
+            let mut header = tar::Header::new_gnu();
+            // bypass the validation of `set_path` to craft malicious entries
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(*kind);
+            header.set_mode(*mode);
+            if *kind == EntryType::Symlink {
+                header.as_old_mut().linkname[..data.len()].copy_from_slice(data);
+                header.set_size(0);
+                header.set_cksum();
+                builder.append(&header, io::empty()).unwrap();
+            } else {
+                header.set_size(data.len() as u64);
+                header.set_cksum();
+                builder.append(&header, *data).unwrap();
+            }
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn test_extract_tar_gz() {
+        let tmp = tempfile::tempdir().unwrap();
+        let archive = tmp.path().join("a");
+        let dest = tmp.path().join("out");
+        tar_gz(
+            &archive,
+            &[
+                ("walletshield", EntryType::Regular, b"bin", 0o4755),
+                ("lib/libx.so.1", EntryType::Regular, b"lib", 0o644),
+                ("lib/libx.so", EntryType::Symlink, b"libx.so.1", 0o777),
+            ],
+        );
+
+        assert_eq!(sniff(&archive).unwrap(), Some(ArchiveKind::TarGz));
+        let files = extract(&archive, ArchiveKind::TarGz, &dest).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("lib/libx.so"),
+                PathBuf::from("lib/libx.so.1"),
+                PathBuf::from("walletshield"),
+            ]
+        );
+        assert_eq!(fs::read(dest.join("lib/libx.so")).unwrap(), b"lib");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dest.join("walletshield"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o7777, 0o755);
+        }
+    }
+
+    #[test]
+    fn test_rejects_escaping_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let archive = tmp.path().join("a");
+        let dest = tmp.path().join("out");
+
+        for entry in [
+            ("../evil", EntryType::Regular, &b"x"[..], 0o644),
+            ("/etc/evil", EntryType::Regular, b"x", 0o644),
+            ("link", EntryType::Symlink, b"../../etc", 0o777),
+            ("link", EntryType::Symlink, b"/etc", 0o777),
+            ("hard", EntryType::Link, b"", 0o644),
+        ] {
+            tar_gz(&archive, &[entry]);
+            assert!(
+                extract(&archive, ArchiveKind::TarGz, &dest).is_err(),
+                "{entry:?} was accepted"
+            );
+        }
+        assert!(!tmp.path().join("evil").exists());
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let archive = tmp.path().join("a");
+        let dest = tmp.path().join("out");
+
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        let opts = zip::write::SimpleFileOptions::default().unix_permissions(0o755);
+        zip.start_file("walletshield.exe", opts).unwrap();
+        zip.write_all(b"MZ").unwrap();
+        zip.start_file("../evil", opts).unwrap();
+        zip.finish().unwrap();
+
+        assert_eq!(sniff(&archive).unwrap(), Some(ArchiveKind::Zip));
+        assert!(extract(&archive, ArchiveKind::Zip, &dest).is_err());
+        assert_eq!(fs::read(dest.join("walletshield.exe")).unwrap(), b"MZ");
+        assert!(!tmp.path().join("evil").exists());
+    }
+}
@@ -0,0 +1,242 @@
+//! Validation of executable headers against the platform they should run on.
+//!
+//! A misconfigured server may answer with an HTML error page, or serve a build
+//! for another architecture. Checking the ELF, Mach-O or PE header before a
+//! binary is installed turns the later, confusing exec failure into a precise
+//! error.
+
+use std::{fmt, fs::File, io::Read, path::Path};
+
+use anyhow::{bail, Result};
+
+/// Number of leading bytes needed to identify a binary.
+const HEADER_LEN: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Elf,
+    MachO,
+    /// A universal Mach-O binary, holding builds for several architectures
+    MachOFat,
+    Pe,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    X64,
+    Arm64,
+    Other(u32),
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arch::X64 => write!(f, "x64"),
+            Arch::Arm64 => write!(f, "arm64"),
+            Arch::Other(m) => write!(f, "machine {m:#x}"),
+        }
+    }
+}
+
+/// Format and architecture of an executable, as declared by its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BinaryInfo {
+    pub format: Format,
+    /// `None` for universal binaries
+    pub arch: Option<Arch>,
+}
+
+impl fmt::Display for BinaryInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.arch {
+            Some(arch) => write!(f, "{:?} {arch} binary", self.format),
+            None => write!(f, "{:?} binary", self.format),
+        }
+    }
+}
+
+/// Parse the header of an executable.
+pub fn identify(header: &[u8]) -> Result<BinaryInfo> {
+    let u16_le = |at: usize| {
+        header
+            .get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let u32_le = |at: usize| {
+        header
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let u32_be = |at: usize| u32_le(at).map(u32::swap_bytes);
+
+    match header {
+        [0x7f, b'E', b'L', b'F', class, data, ..] => {
+            if *class != 2 || *data != 1 {
+                bail!(
+                    "unsupported ELF binary (class {class}, encoding {data}); \
+                     a 64-bit little-endian build is required"
+                );
+            }
+            let Some(machine) = u16_le(18) else {
+                bail!("truncated ELF header");
+            };
+            let arch = match machine {
+                0x3e => Arch::X64,
+                0xb7 => Arch::Arm64,
+                m => Arch::Other(m.into()),
+            };
+            Ok(BinaryInfo {
+                format: Format::Elf,
+                arch: Some(arch),
+            })
+        }
+        [0xcf, 0xfa, 0xed, 0xfe, ..] => {
+            let Some(cpu) = u32_le(4) else {
+                bail!("truncated Mach-O header");
+            };
+            let arch = match cpu {
+                0x0100_0007 => Arch::X64,
+                0x0100_000c => Arch::Arm64,
+                c => Arch::Other(c),
+            };
+            Ok(BinaryInfo {
+                format: Format::MachO,
+                arch: Some(arch),
+            })
+        }
+        // also the magic of Java class files, which store a version instead
+        // of a (small) number of architectures
+        [0xca, 0xfe, 0xba, 0xbe, ..] if u32_be(4).is_some_and(|n| (1..=16).contains(&n)) => {
+            Ok(BinaryInfo {
+                format: Format::MachOFat,
+                arch: None,
+            })
+        }
+        [b'M', b'Z', ..] => {
+            let Some(offset) = u32_le(0x3c).map(|o| o as usize) else {
+                bail!("truncated PE header");
+            };
+            if header.get(offset..offset + 4) != Some(b"PE\0\0") {
+                bail!("MZ executable without a PE header in its first {HEADER_LEN} bytes");
+            }
+            let Some(machine) = u16_le(offset + 4) else {
+                bail!("truncated PE header");
+            };
+            let arch = match machine {
+                0x8664 => Arch::X64,
+                0xaa64 => Arch::Arm64,
+                m => Arch::Other(m.into()),
+            };
+            Ok(BinaryInfo {
+                format: Format::Pe,
+                arch: Some(arch),
+            })
+        }
+        _ if looks_like_text(header) => {
+            bail!("not an executable but a text document (e.g. an HTML error page)")
+        }
+        _ => bail!("not a recognized executable format"),
+    }
+}
+
+/// Check that the binary at `path` runs on `platform_arch`, as returned by
+/// `get_platform_arch()`.
+pub fn check_binary(path: &Path, platform_arch: &str) -> Result<BinaryInfo> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    File::open(path)?
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+
+    let info = match identify(&header) {
+        Ok(info) => info,
+        Err(e) => bail!("{} is invalid: {e}", path.display()),
+    };
+
+    let ok = matches!(
+        (platform_arch, info.format, info.arch),
+        ("linux-x64", Format::Elf, Some(Arch::X64))
+            | ("linux-arm64", Format::Elf, Some(Arch::Arm64))
+            | ("macos", Format::MachOFat, _)
+            | ("macos", Format::MachO, Some(Arch::X64 | Arch::Arm64))
+            | ("windows-x64", Format::Pe, Some(Arch::X64))
+    );
+    if !ok {
+        bail!(
+            "{} is a {info}, which does not run on {platform_arch}",
+            path.display()
+        );
+    }
+    Ok(info)
+}
+
+fn looks_like_text(header: &[u8]) -> bool {
+    let start = header.iter().position(|b| !b.is_ascii_whitespace());
+    start.is_some_and(|i| header[i] == b'<' || header[i] == b'{')
+        || header
+            .iter()
+            .all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+}
+
+/// A minimal header of a binary for `platform_arch`, for tests.
+#[cfg(test)]
+pub(crate) fn stub(platform_arch: &str) -> Vec<u8> {
+    let mut bin = vec![0; 128];
+    match platform_arch {
+        "linux-x64" | "linux-arm64" => {
+            bin[..6].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1]);
+            let machine: u16 = if platform_arch == "linux-x64" {
+                0x3e
+            } else {
+                0xb7
+            };
+            bin[18..20].copy_from_slice(&machine.to_le_bytes());
+        }
+        "macos" => {
+            bin[..4].copy_from_slice(&[0xcf, 0xfa, 0xed, 0xfe]);
+            bin[4..8].copy_from_slice(&0x0100_000cu32.to_le_bytes());
+        }
+        "windows-x64" => {
+            bin[..2].copy_from_slice(b"MZ");
+            bin[0x3c..0x40].copy_from_slice(&64u32.to_le_bytes());
+            bin[64..68].copy_from_slice(b"PE\0\0");
+            bin[68..70].copy_from_slice(&0x8664u16.to_le_bytes());
+        }
+        _ => panic!("no stub for {platform_arch}"),
+    }
+    bin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identify_platform_stubs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("walletshield");
+        for platform_arch in ["linux-x64", "linux-arm64", "macos", "windows-x64"] {
+            std::fs::write(&path, stub(platform_arch)).unwrap();
+            assert!(
+                check_binary(&path, platform_arch).is_ok(),
+                "{platform_arch}"
+            );
+        }
+
+        std::fs::write(&path, stub("linux-arm64")).unwrap();
+        let err = check_binary(&path, "linux-x64").unwrap_err().to_string();
+        assert!(err.contains("Elf arm64 binary"), "{err}");
+        assert!(check_binary(&path, "windows-x64").is_err());
+    }
+
+    #[test]
+    fn test_rejects_non_executables() {
+        let html = b"\n<!DOCTYPE html><html><body>502 Bad Gateway</body></html>";
+        let err = identify(html).unwrap_err().to_string();
+        assert!(err.contains("HTML"), "{err}");
+
+        assert!(identify(&[0x7f, b'E', b'L', b'F', 1, 1, 0, 0]).is_err());
+        assert!(identify(b"MZ").is_err());
+        assert!(identify(&[0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52]).is_err());
+        assert!(identify(&[0, 1, 2, 3]).is_err());
+    }
+}
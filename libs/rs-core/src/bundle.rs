@@ -0,0 +1,305 @@
+//! Offline export and import of network bundles.
+//!
+//! A bundle is a `.tar.gz` holding `bundle.json` (the network's metadata and
+//! the platform its binaries are built for) and the network's assets under
+//! `assets/`. Imports are verified against the recorded hashes and sizes, then
+//! installed through the same staging path as downloaded assets.
+//!
+//! Bundles are not signed. The recorded hashes only catch corruption: whoever
+//! can write a bundle can change its assets and their hashes alike, so only
+//! bundles from a trusted source should be imported.
+
+use std::{
+    fs::{self, File},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{ensure, Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::{self, ArchiveKind},
+    install::{asset_path, Installer},
+    lock::lock_idle,
+    paths::AppPaths,
+    registry::{validate_network_id, AssetRecord, NetworkMeta, NetworkRegistry},
+    store::hash_file,
+    utils::unix_now,
+};
+
+const FILE_MANIFEST: &str = "bundle.json";
+const DIR_ASSETS: &str = "assets";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    format_version: u32,
+    platform_arch: String,
+    network: NetworkMeta,
+}
+
+/// Pack an installed network into the bundle file `out`.
+pub fn export_network(
+    paths: &AppPaths,
+    platform_arch: &str,
+    network_id: &str,
+    out: &Path,
+) -> Result<()> {
+    let registry = NetworkRegistry::new(paths);
+    let meta = registry.load(network_id)?;
+    ensure!(
+        !meta.assets.is_empty(),
+        "network {network_id} has no recorded assets; connect to it again before exporting"
+    );
+
+    // never pass on assets that changed since they were installed
+    let dir = registry.dir_network(network_id);
+    for asset in &meta.assets {
+        verify(&dir, asset)?;
+    }
+
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION,
+        platform_arch: platform_arch.to_owned(),
+        network: meta,
+    };
+    let json = serde_json::to_vec_pretty(&manifest)?;
+
+    let gz = GzEncoder::new(File::create(out)?, Compression::default());
+    let mut tar = tar::Builder::new(gz);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, FILE_MANIFEST, json.as_slice())?;
+
+    for asset in &manifest.network.assets {
+        tar.append_path_with_name(
+            dir.join(&asset.name),
+            format!("{DIR_ASSETS}/{}", asset.name),
+        )?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Verify and install the network packed in `bundle`, returning its ID.
+///
+/// The bundle is not authenticated, see the module documentation.
+pub async fn import_network(
+    paths: &AppPaths,
+    platform_arch: &str,
+    bundle: &Path,
+) -> Result<String> {
+    let dir_staging =
+        paths
+            .dir_staging()
+            .join(format!("import-{}-{}", std::process::id(), unix_now()));
+
+    let (src, dest) = (bundle.to_path_buf(), dir_staging.clone());
+    let result = async {
+        tokio::task::spawn_blocking(move || {
+            ensure!(
+                archive::sniff(&src)? == Some(ArchiveKind::TarGz),
+                "{} is not a network bundle",
+                src.display()
+            );
+            archive::extract(&src, ArchiveKind::TarGz, &dest)
+        })
+        .await??;
+        install(paths, platform_arch, &dir_staging, bundle).await
+    }
+    .await;
+
+    if dir_staging.exists() {
+        tokio::fs::remove_dir_all(&dir_staging).await?;
+    }
+    result
+}
+
+async fn install(
+    paths: &AppPaths,
+    platform_arch: &str,
+    dir_staging: &Path,
+    bundle: &Path,
+) -> Result<String> {
+    let manifest: Manifest = serde_json::from_slice(
+        &tokio::fs::read(dir_staging.join(FILE_MANIFEST))
+            .await
+            .context("bundle has no manifest")?,
+    )
+    .context("invalid bundle manifest")?;
+
+    ensure!(
+        manifest.format_version == FORMAT_VERSION,
+        "unsupported bundle format version {}",
+        manifest.format_version
+    );
+    ensure!(
+        manifest.platform_arch == platform_arch,
+        "bundle is for platform {}, not {platform_arch}",
+        manifest.platform_arch
+    );
+
+    let meta = manifest.network;
+    validate_network_id(&meta.id)?;
+    let _idle = lock_idle(paths, &meta.id)?;
+    ensure!(!meta.assets.is_empty(), "bundle contains no assets");
+
+    // the names come from the bundle, so must not lead out of its assets
+    let files = meta
+        .assets
+        .iter()
+        .map(|a| asset_path(&a.name, "walletshield"))
+        .collect::<Result<Vec<_>>>()?;
+
+    let dir_assets = dir_staging.join(DIR_ASSETS);
+    let (dir, assets) = (dir_assets.clone(), meta.assets.clone());
+    tokio::task::spawn_blocking(move || assets.iter().try_for_each(|a| verify(&dir, a))).await??;
+
+    let registry = NetworkRegistry::new(paths);
+    let dir_network = registry.dir_network(&meta.id);
+    tokio::fs::create_dir_all(&dir_network).await?;
+
+    let installer = Installer {
+        dir: Arc::new(dir_network),
+        platform_arch: Arc::from(platform_arch.to_owned()),
+        store: registry.store(),
+    };
+    let records = installer
+        .install_tree(&dir_assets, &files, "walletshield")
+        .await?;
+
+    let source = meta
+        .source_url
+        .unwrap_or_else(|| format!("bundle:{}", bundle.display()));
+    registry.record_install(&meta.id, &source, records, meta.remote)?;
+    Ok(meta.id)
+}
+
+/// Ensure the asset below `dir` matches its recorded size and hash.
+fn verify(dir: &Path, asset: &AssetRecord) -> Result<()> {
+    let path = dir.join(&asset.name);
+    let size = fs::metadata(&path)
+        .with_context(|| format!("asset {} is missing", asset.name))?
+        .len();
+    ensure!(
+        size == asset.size && hash_file(&path)? == asset.sha256,
+        "asset {} does not match its recorded hash",
+        asset.name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLATFORM_ARCH: &str = "linux-x64";
+
+    fn install_fake(registry: &NetworkRegistry, id: &str) {
+        let dir = registry.dir_network(id);
+        fs::create_dir_all(&dir).unwrap();
+        let mut assets = Vec::new();
+        let bin = crate::binfmt::stub(PLATFORM_ARCH);
+        for (name, data) in [("client.toml", &b"cfg"[..]), ("walletshield", &bin)] {
+            fs::write(dir.join(name), data).unwrap();
+            assets.push(AssetRecord {
+                name: name.into(),
+                sha256: hash_file(&dir.join(name)).unwrap(),
+                size: data.len() as u64,
+            });
+        }
+        registry
+            .record_install(id, "https://example.org/net", assets, Default::default())
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_export_import_roundtrip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths = AppPaths::from_dir(tmp.path().join("data"));
+        let registry = NetworkRegistry::new(&paths);
+        let bundle = tmp.path().join("net.tar.gz");
+
+        install_fake(&registry, "net");
+        export_network(&paths, PLATFORM_ARCH, "net", &bundle).unwrap();
+        registry.remove("net").unwrap();
+
+        let err = import_network(&paths, "windows-x64", &bundle).await;
+        assert!(err.unwrap_err().to_string().contains("linux-x64"));
+
+        let id = import_network(&paths, PLATFORM_ARCH, &bundle)
+            .await
+            .unwrap();
+        assert_eq!(id, "net");
+        let meta = registry.load("net").unwrap();
+        assert_eq!(meta.source_url.as_deref(), Some("https://example.org/net"));
+        assert_eq!(meta.assets.len(), 2);
+        assert_eq!(
+            fs::read(registry.dir_network("net").join("walletshield")).unwrap(),
+            crate::binfmt::stub(PLATFORM_ARCH)
+        );
+        assert!(fs::read_dir(paths.dir_staging()).unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_import_refuses_escaping_assets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths = AppPaths::from_dir(tmp.path().join("data"));
+        // the asset matches its record, so only its name gives it away
+        fs::write(tmp.path().join("escape"), "x").unwrap();
+        let sha256 = hash_file(&tmp.path().join("escape")).unwrap();
+
+        for name in ["../escape", "/escape", "meta.json", "lib/walletshield"] {
+            let manifest = Manifest {
+                format_version: FORMAT_VERSION,
+                platform_arch: PLATFORM_ARCH.into(),
+                network: NetworkMeta {
+                    id: "net".into(),
+                    assets: vec![AssetRecord {
+                        name: name.into(),
+                        sha256: sha256.clone(),
+                        size: 1,
+                    }],
+                    ..Default::default()
+                },
+            };
+            let bundle = tmp.path().join("net.tar.gz");
+            let gz = GzEncoder::new(File::create(&bundle).unwrap(), Compression::fast());
+            let mut tar = tar::Builder::new(gz);
+            for (path, data) in [
+                (FILE_MANIFEST, serde_json::to_vec(&manifest).unwrap()),
+                ("escape", b"x".to_vec()),
+            ] {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                tar.append_data(&mut header, path, &data[..]).unwrap();
+            }
+            tar.into_inner().unwrap().finish().unwrap();
+
+            let err = import_network(&paths, PLATFORM_ARCH, &bundle).await;
+            assert!(err.is_err(), "{name}");
+            assert!(!paths.dir_networks().join("escape").exists());
+        }
+    }
+
+    #[test]
+    fn test_export_refuses_modified_assets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths = AppPaths::from_dir(tmp.path().join("data"));
+        let registry = NetworkRegistry::new(&paths);
+
+        install_fake(&registry, "net");
+        fs::write(registry.dir_network("net").join("client.toml"), "CFG").unwrap();
+
+        let out = tmp.path().join("net.tar.gz");
+        assert!(export_network(&paths, PLATFORM_ARCH, "net", &out).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Discovery of available networks from the catalog published at `url_network`.
+//!
+//! The catalog is fetched from `<url_network>/networks.json` and cached in the
+//! data directory, so that a recent copy is available offline.
+
+use std::{fs, time::Duration};
+
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::{net::download, paths::AppPaths, utils::unix_now};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkStatus {
+    Active,
+    Maintenance,
+    Deprecated,
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogEntry {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Chains served by the network's walletshield
+    #[serde(default)]
+    pub chains: Vec<String>,
+    #[serde(default)]
+    pub status: NetworkStatus,
+    /// Oldest client version able to use the network
+    pub min_client_version: Option<String>,
+    /// Newest client version able to use the network
+    pub max_client_version: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Catalog {
+    /// Unix timestamp (seconds) of when the catalog was fetched
+    #[serde(default)]
+    pub fetched_at: u64,
+    /// Set when the catalog could not be refreshed and a stale copy is used
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    pub networks: Vec<CatalogEntry>,
+}
+
+impl Catalog {
+    pub fn get(&self, network_id: &str) -> Option<&CatalogEntry> {
+        self.networks.iter().find(|n| n.id == network_id)
+    }
+}
+
+/// How long a cached catalog is used before it is refreshed
+pub const CATALOG_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Get the network catalog, using the cached copy if younger than `max_age`.
+///
+/// If the catalog cannot be fetched, a cached copy of any age is returned
+/// (marked as stale) before giving up.
+pub async fn fetch_catalog(
+    paths: &AppPaths,
+    url_network: &str,
+    max_age: Duration,
+) -> Result<Catalog> {
+    let cached = load_cached(paths);
+
+    if let Some(c) = &cached {
+        if unix_now().saturating_sub(c.fetched_at) < max_age.as_secs() {
+            return Ok(c.clone());
+        }
+    }
+
+    let url = format!("{url_network}/networks.json");
+    let fetched = async {
+        let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+        let mut buf = Vec::new();
+        download(&client, &url, &mut buf, None, None, None).await?;
+        let mut catalog: Catalog = serde_json::from_slice(&buf)
+            .with_context(|| format!("invalid network catalog at {url}"))?;
+        catalog.fetched_at = unix_now();
+        Ok::<_, anyhow::Error>(catalog)
+    }
+    .await;
+
+    match (fetched, cached) {
+        (Ok(catalog), _) => {
+            fs::write(paths.path_catalog(), serde_json::to_vec_pretty(&catalog)?)?;
+            Ok(catalog)
+        }
+        (Err(e), Some(mut stale)) => {
+            eprintln!("Failed to refresh network catalog, using cached copy: {e:#}");
+            stale.stale = true;
+            Ok(stale)
+        }
+        (Err(e), None) => Err(e.context("failed to fetch network catalog")),
+    }
+}
+
+fn load_cached(paths: &AppPaths) -> Option<Catalog> {
+    let s = fs::read_to_string(paths.path_catalog()).ok()?;
+    serde_json::from_str(&s).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CATALOG_JSON: &str = r#"{
+        "networks": [
+            {
+                "id": "test-net",
+                "name": "Test Network",
+                "chains": ["ethereum", "zcash"],
+                "status": "active",
+                "minClientVersion": "0.1.0"
+            },
+            { "id": "old-net", "name": "Old", "status": "retired" }
+        ]
+    }"#;
+
+    #[test]
+    fn test_parse_catalog() {
+        let catalog: Catalog = serde_json::from_str(CATALOG_JSON).unwrap();
+        let net = catalog.get("test-net").unwrap();
+        assert_eq!(net.chains, vec!["ethereum", "zcash"]);
+        assert_eq!(net.status, NetworkStatus::Active);
+        assert_eq!(net.description, "");
+        assert_eq!(net.min_client_version.as_deref(), Some("0.1.0"));
+        assert_eq!(net.max_client_version, None);
+        assert_eq!(
+            catalog.get("old-net").unwrap().status,
+            NetworkStatus::Unknown
+        );
+        assert!(catalog.get("missing").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_stale_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths = AppPaths::from_dir(tmp.path().to_path_buf());
+        let unreachable = "http://127.0.0.1:1";
+
+        let err = fetch_catalog(&paths, unreachable, Duration::ZERO).await;
+        assert!(err.is_err());
+
+        fs::write(paths.path_catalog(), CATALOG_JSON).unwrap();
+        let catalog = fetch_catalog(&paths, unreachable, Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(catalog.stale);
+        assert_eq!(catalog.networks.len(), 2);
+    }
+}
@@ -0,0 +1,447 @@
+//! A handle on the client of a network.
+//!
+//! `NetworkClient` prepares a network and runs its walletshield in the
+//! background under a supervisor. The handle is cheap to clone, so that it
+//! can be shared between the tasks that start, stop and observe the client.
+//! The client is stopped once the last handle is dropped.
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast::error::RecvError, watch};
+
+use crate::{
+    context::AppContext,
+    events::{Events, LifecycleEvent},
+    integrity::TamperError,
+    listen::claim_listen_address,
+    lock::{running_session, AlreadyRunning, SessionLock},
+    logs::NetworkLog,
+    network_install, prepare_network,
+    readiness::{Readiness, ReadyState},
+    registry::validate_network_id,
+    sandbox::SandboxReport,
+    start_network_client,
+    supervisor::{ProcessState, Shutdown, Supervisor, SupervisorStatus},
+};
+
+/// How a session ended; errors are kept as messages so that every waiter
+/// can receive them.
+type Outcome = Result<Shutdown, String>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientStatus {
+    pub network_id: String,
+    /// Whether the client is being started or is running
+    pub running: bool,
+    pub ready: ReadyState,
+    /// Address walletshield listens on, which differs from the configured
+    /// one if its port was busy
+    pub listen_address: Option<String>,
+    pub process: SupervisorStatus,
+    /// Why the last session failed, if it did
+    pub error: Option<String>,
+    /// Protections of the sandbox applied by the last walletshield spawned
+    #[serde(default)]
+    pub sandbox: Option<SandboxReport>,
+}
+
+#[derive(Clone)]
+pub struct NetworkClient {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    ctx: AppContext,
+    network_id: String,
+    readiness: Arc<Readiness>,
+    log: Arc<NetworkLog>,
+    events: Events,
+    sandbox: Arc<Mutex<Option<SandboxReport>>>,
+    session: Mutex<Option<Session>>,
+}
+
+/// A single run of the client, from `start` until it ends.
+struct Session {
+    supervisor: Arc<Supervisor>,
+    listen_address: String,
+    outcome: watch::Receiver<Option<Outcome>>,
+}
+
+impl Session {
+    fn is_running(&self) -> bool {
+        // the sender is dropped without an outcome if `start` was cancelled
+        self.outcome.borrow().is_none() && self.outcome.has_changed().is_ok()
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.get_mut().unwrap() {
+            session.supervisor.stop();
+        }
+    }
+}
+
+impl NetworkClient {
+    /// Create a handle on the client of a network, without starting it.
+    pub fn new(ctx: AppContext, network_id: &str) -> Result<Self> {
+        validate_network_id(network_id)?;
+        let log = NetworkLog::open(&ctx.paths, network_id, ctx.config.logs.clone())?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                ctx,
+                network_id: network_id.to_owned(),
+                readiness: Arc::new(Readiness::new()),
+                log: Arc::new(log),
+                events: Events::new(network_id),
+                sandbox: Arc::default(),
+                session: Mutex::new(None),
+            }),
+        })
+    }
+
+    pub fn network_id(&self) -> &str {
+        &self.inner.network_id
+    }
+
+    pub fn readiness(&self) -> &Arc<Readiness> {
+        &self.inner.readiness
+    }
+
+    pub fn log(&self) -> &Arc<NetworkLog> {
+        &self.inner.log
+    }
+
+    /// The lifecycle events of the client, which outlive its sessions.
+    pub fn events(&self) -> &Events {
+        &self.inner.events
+    }
+
+    pub fn is_running(&self) -> bool {
+        let session = self.inner.session.lock().unwrap();
+        session.as_ref().is_some_and(Session::is_running)
+    }
+
+    /// Install or update the network as needed and start its client in the
+    /// background.
+    ///
+    /// Returns once the client was started; use `wait` to learn how it ends.
+    /// Fails if the client is already running or the network cannot be
+    /// prepared.
+    pub async fn start(&self) -> Result<()> {
+        self.start_or_reinstall(|_| async { Ok(false) }).await
+    }
+
+    /// Like `start`, but if the binary of the network was tampered with, ask
+    /// `confirm_reinstall` whether to reinstall the network and start it
+    /// anyway.
+    ///
+    /// The network stays locked for the session throughout, so that no other
+    /// session can start it while the answer is awaited.
+    pub async fn start_or_reinstall<F, Fut>(&self, confirm_reinstall: F) -> Result<()>
+    where
+        F: FnOnce(&anyhow::Error) -> Fut,
+        Fut: Future<Output = Result<bool>>,
+    {
+        let inner = &self.inner;
+        let supervisor = Arc::new(
+            Supervisor::new(inner.ctx.config.supervisor.clone()).with_events(inner.events.clone()),
+        );
+        let (outcome, rx) = watch::channel(None);
+        let (ctx, lock) = {
+            let mut session = inner.session.lock().unwrap();
+            if session.as_ref().is_some_and(Session::is_running) {
+                bail!(
+                    "the client of network {} is already running",
+                    inner.network_id
+                );
+            }
+            let (ctx, lock) = self.claim().inspect_err(|e| {
+                inner.events.emit(LifecycleEvent::Error {
+                    message: format!("{e:#}"),
+                })
+            })?;
+            *session = Some(Session {
+                supervisor: supervisor.clone(),
+                listen_address: ctx.config.walletshield_listen_address.clone(),
+                outcome: rx,
+            });
+            (ctx, lock)
+        };
+
+        let prepared =
+            prepare_or_reinstall(&ctx, &inner.network_id, &inner.events, confirm_reinstall).await;
+        if let Err(e) = prepared {
+            let message = format!("{e:#}");
+            inner.events.emit(LifecycleEvent::Error {
+                message: message.clone(),
+            });
+            outcome.send_replace(Some(Err(message)));
+            return Err(e);
+        }
+
+        let network_id = inner.network_id.clone();
+        let readiness = inner.readiness.clone();
+        let log = inner.log.clone();
+        let events = inner.events.clone();
+        let sandbox = inner.sandbox.clone();
+        tokio::spawn(async move {
+            let publisher = tokio::spawn(publish_status(
+                lock,
+                events.clone(),
+                supervisor.clone(),
+                readiness.clone(),
+                sandbox.clone(),
+            ));
+            let result = start_network_client(
+                ctx,
+                &network_id,
+                &supervisor,
+                &readiness,
+                &log,
+                &events,
+                &sandbox,
+            )
+            .await
+            .map_err(|e| format!("{e:#}"));
+            // release the lock before the session is reported as ended
+            publisher.abort();
+            let _ = publisher.await;
+            if let Err(message) = &result {
+                events.emit(LifecycleEvent::Error {
+                    message: message.clone(),
+                });
+            }
+            outcome.send_replace(Some(result));
+        });
+        Ok(())
+    }
+
+    /// Choose the listen address of a new session and lock the network and
+    /// address for it, returning the context of the session.
+    fn claim(&self) -> Result<(AppContext, SessionLock)> {
+        let inner = &self.inner;
+        let mut ctx = inner.ctx.clone();
+
+        // a session of another process would hold the port, which is better
+        // reported as such
+        if let Some(info) = running_session(&ctx.paths, &inner.network_id)? {
+            return Err(AlreadyRunning {
+                what: format!("network {}", inner.network_id),
+                info: Some(info),
+            }
+            .into());
+        }
+
+        let cfg = &ctx.config.listen;
+        let (listen_address, lock) =
+            claim_listen_address(&ctx.config.walletshield_listen_address, cfg, |addr| {
+                match SessionLock::acquire(&ctx.paths, &inner.network_id, addr) {
+                    Ok(lock) => Ok(Some(lock)),
+                    // another session chose the same free port first
+                    Err(e)
+                        if cfg.fallback
+                            && e.downcast_ref::<AlreadyRunning>()
+                                .is_some_and(|e| e.is_listen_address()) =>
+                    {
+                        Ok(None)
+                    }
+                    Err(e) => Err(e),
+                }
+            })?;
+        ctx.config.walletshield_listen_address = listen_address;
+        Ok((ctx, lock))
+    }
+
+    /// Stop the client, returning how it ended.
+    ///
+    /// Does nothing if the client is not running.
+    pub async fn stop(&self) -> Result<Shutdown> {
+        let (supervisor, mut outcome) = {
+            let session = self.inner.session.lock().unwrap();
+            match &*session {
+                Some(s) if s.is_running() => (s.supervisor.clone(), s.outcome.clone()),
+                _ => return Ok(Shutdown::Graceful),
+            }
+        };
+        supervisor.stop();
+        wait_outcome(&mut outcome).await
+    }
+
+    pub async fn restart(&self) -> Result<()> {
+        self.stop().await?;
+        self.start().await
+    }
+
+    /// Wait until the client ends, without stopping it.
+    pub async fn wait(&self) -> Result<Shutdown> {
+        let mut outcome = {
+            let session = self.inner.session.lock().unwrap();
+            match &*session {
+                Some(s) => s.outcome.clone(),
+                None => bail!(
+                    "the client of network {} was not started",
+                    self.inner.network_id
+                ),
+            }
+        };
+        wait_outcome(&mut outcome).await
+    }
+
+    pub fn status(&self) -> ClientStatus {
+        let session = self.inner.session.lock().unwrap();
+        let (running, listen_address, process, error) = match &*session {
+            Some(s) => (
+                s.is_running(),
+                Some(s.listen_address.clone()),
+                s.supervisor.status(),
+                s.outcome
+                    .borrow()
+                    .as_ref()
+                    .and_then(|o| o.as_ref().err().cloned()),
+            ),
+            None => (
+                false,
+                None,
+                SupervisorStatus {
+                    state: ProcessState::Stopped,
+                    ..Default::default()
+                },
+                None,
+            ),
+        };
+        ClientStatus {
+            network_id: self.inner.network_id.clone(),
+            running,
+            ready: self.inner.readiness.state(),
+            listen_address,
+            process,
+            error,
+            sandbox: self.inner.sandbox.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Prepare a network, reinstalling it first if its binary was tampered with
+/// and `confirm_reinstall` agrees.
+async fn prepare_or_reinstall<F, Fut>(
+    ctx: &AppContext,
+    network_id: &str,
+    events: &Events,
+    confirm_reinstall: F,
+) -> Result<()>
+where
+    F: FnOnce(&anyhow::Error) -> Fut,
+    Fut: Future<Output = Result<bool>>,
+{
+    let e = match prepare_network(ctx, network_id, events).await {
+        Err(e) if e.downcast_ref::<TamperError>().is_some() => e,
+        result => return result,
+    };
+    if !confirm_reinstall(&e).await? {
+        return Err(e);
+    }
+    network_install(ctx, network_id, events).await?;
+    prepare_network(ctx, network_id, events).await
+}
+
+/// Publish the status of a session to other processes whenever an event
+/// occurs, for as long as the session holds `lock`.
+async fn publish_status(
+    mut lock: SessionLock,
+    events: Events,
+    supervisor: Arc<Supervisor>,
+    readiness: Arc<Readiness>,
+    sandbox: Arc<Mutex<Option<SandboxReport>>>,
+) {
+    let mut rx = events.subscribe();
+    loop {
+        let status = ClientStatus {
+            network_id: events.network_id().to_owned(),
+            running: true,
+            ready: readiness.state(),
+            listen_address: Some(lock.info().listen_address.clone()),
+            process: supervisor.status(),
+            error: None,
+            sandbox: sandbox.lock().unwrap().clone(),
+        };
+        if let Err(e) = lock.set_status(status) {
+            eprintln!("Failed to publish the session status: {e:#}");
+        }
+        if let Err(RecvError::Closed) = rx.recv().await {
+            break;
+        }
+    }
+}
+
+async fn wait_outcome(outcome: &mut watch::Receiver<Option<Outcome>>) -> Result<Shutdown> {
+    // the returned borrow of the value must not be held across awaits
+    let result = outcome.wait_for(Option::is_some).await.map(|o| o.clone());
+    match result {
+        Ok(Some(Ok(shutdown))) => Ok(shutdown),
+        Ok(Some(Err(e))) => Err(anyhow!(e)),
+        // `start` was cancelled before the client was started
+        _ => Ok(Shutdown::Graceful),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lock::AlreadyRunning, paths::AppPaths};
+
+    fn ctx(dir: &std::path::Path) -> AppContext {
+        // nothing listens on the discard port, so every download fails fast
+        let config = r#"{
+            "apiListenAddress": "127.0.0.1:0",
+            "urlNetwork": "http://127.0.0.1:9",
+            "walletshieldListenAddress": ":0"
+        }"#;
+        AppContext::from_paths(
+            AppPaths::from_dir(dir.to_path_buf()),
+            "0.1.0",
+            config,
+            "linux-x64".into(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_failed_start_is_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(NetworkClient::new(ctx(tmp.path()), "../etc").is_err());
+
+        let client = NetworkClient::new(ctx(tmp.path()), "net").unwrap();
+        assert!(client.wait().await.is_err());
+        assert_eq!(client.status().process.state, ProcessState::Stopped);
+
+        assert!(client.start().await.is_err());
+        let status = client.status();
+        assert!(!status.running);
+        assert!(status.error.is_some());
+        assert!(client.wait().await.is_err());
+        assert_eq!(client.stop().await.unwrap(), Shutdown::Graceful);
+    }
+
+    #[tokio::test]
+    async fn test_start_fails_while_locked() {
+        let tmp = tempfile::tempdir().unwrap();
+        let ctx = ctx(tmp.path());
+        let _lock = SessionLock::acquire(&ctx.paths, "net", ":0").unwrap();
+
+        let client = NetworkClient::new(ctx, "net").unwrap();
+        let mut events = client.events().subscribe();
+        let err = client.start().await.unwrap_err();
+        assert!(err.downcast_ref::<AlreadyRunning>().is_some(), "{err:#}");
+        assert!(!client.is_running());
+        assert!(matches!(
+            events.try_recv().unwrap().event,
+            LifecycleEvent::Error { .. }
+        ));
+    }
+}
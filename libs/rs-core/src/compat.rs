@@ -0,0 +1,119 @@
+//! Compatibility of networks with this client's version.
+//!
+//! A network's catalog entry may declare the range of client versions that
+//! understand its `client.toml` format and walletshield flags. Connecting with
+//! a client outside that range is refused instead of launching a walletshield
+//! that would fail in obscure ways.
+
+use anyhow::{bail, Context, Result};
+use semver::Version;
+
+use crate::{
+    catalog::{fetch_catalog, CatalogEntry, CATALOG_MAX_AGE},
+    paths::AppPaths,
+};
+
+/// Check that the client `version` lies within the range the network supports
+/// (both bounds inclusive).
+///
+/// A bound that cannot be parsed is ignored with a warning, as the client
+/// cannot tell whether it is excluded.
+pub fn check_client_version(entry: &CatalogEntry, version: &str) -> Result<()> {
+    let current =
+        Version::parse(version).with_context(|| format!("invalid client version {version:?}"))?;
+    let bound = |v: &Option<String>| {
+        let v = v.as_deref()?;
+        match Version::parse(v) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                eprintln!(
+                    "Warning: ignoring invalid client version {v:?} in the catalog \
+                     entry of {}: {e}",
+                    entry.id
+                );
+                None
+            }
+        }
+    };
+
+    if let Some(min) = bound(&entry.min_client_version) {
+        if current < min {
+            bail!(
+                "network {} requires client version {min} or newer, but this is {current}; \
+                 please upgrade the client",
+                entry.id
+            );
+        }
+    }
+    if let Some(max) = bound(&entry.max_client_version) {
+        if current > max {
+            bail!(
+                "network {} supports client versions up to {max}, but this is {current}; \
+                 please use an older client or another network",
+                entry.id
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Refuse to use a network whose catalog entry excludes the client
+/// `client_version`.
+///
+/// Networks missing from the catalog, or a catalog that cannot be fetched at
+/// all, are not treated as incompatible.
+pub async fn ensure_compatible(
+    paths: &AppPaths,
+    url_network: &str,
+    network_id: &str,
+    client_version: &str,
+) -> Result<()> {
+    let catalog = match fetch_catalog(paths, url_network, CATALOG_MAX_AGE).await {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            eprintln!("Failed to check network compatibility: {e:#}");
+            return Ok(());
+        }
+    };
+    match catalog.get(network_id) {
+        Some(entry) => check_client_version(entry, client_version),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(min: Option<&str>, max: Option<&str>) -> CatalogEntry {
+        CatalogEntry {
+            id: "test-net".into(),
+            name: "Test".into(),
+            description: String::new(),
+            chains: Vec::new(),
+            status: Default::default(),
+            min_client_version: min.map(Into::into),
+            max_client_version: max.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn test_check_client_version() {
+        assert!(check_client_version(&entry(None, None), "0.1.0").is_ok());
+        assert!(check_client_version(&entry(Some("0.1.0"), Some("0.1.0")), "0.1.0").is_ok());
+        assert!(check_client_version(&entry(Some("0.2.0"), None), "1.0.0").is_ok());
+
+        let err = check_client_version(&entry(Some("0.2.0"), None), "0.1.9").unwrap_err();
+        assert!(err.to_string().contains("upgrade"));
+        assert!(check_client_version(&entry(None, Some("0.1.0")), "0.2.0").is_err());
+        assert!(check_client_version(&entry(None, None), "latest").is_err());
+    }
+
+    #[test]
+    fn test_invalid_bounds_are_ignored() {
+        assert!(check_client_version(&entry(Some("latest"), None), "0.1.0").is_ok());
+        assert!(check_client_version(&entry(None, Some("1.x")), "0.1.0").is_ok());
+        // a valid bound still applies
+        assert!(check_client_version(&entry(Some("latest"), Some("0.0.1")), "0.1.0").is_err());
+    }
+}
@@ -1,16 +1,228 @@
-use std::fs;
+use std::{collections::BTreeMap, fs};
 
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::paths::AppPaths;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AppConfig {
     pub api_listen_address: String,
     pub url_network: String,
     pub walletshield_listen_address: String,
+    #[serde(default)]
+    pub gc: GcConfig,
+    #[serde(default)]
+    pub updates: UpdateConfig,
+    #[serde(default)]
+    pub supervisor: SupervisorConfig,
+    #[serde(default)]
+    pub logs: LogConfig,
+    #[serde(default)]
+    pub listen: ListenConfig,
+    #[serde(default)]
+    pub router: RouterConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+}
+
+/// Garbage collection of the networks directory.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GcConfig {
+    /// Remove networks not connected to for this many days (`0` = never)
+    pub max_idle_days: u64,
+    /// Cap on the total size of installed networks in bytes (`0` = no cap)
+    pub max_total_bytes: u64,
+    /// Collect garbage after every successful connect, which removes idle
+    /// networks without asking; off unless enabled
+    pub after_connect: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_days: 90,
+            max_total_bytes: 0,
+            after_connect: false,
+        }
+    }
+}
+
+/// Checking installed networks for new assets.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UpdateConfig {
+    /// Download outdated assets when connecting to a network
+    pub apply_on_connect: bool,
+    /// Seconds between background update checks (`0` = disabled)
+    pub check_interval_secs: u64,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self {
+            apply_on_connect: true,
+            check_interval_secs: 6 * 60 * 60,
+        }
+    }
+}
+
+/// Restarting walletshield after unexpected exits.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SupervisorConfig {
+    /// Delay before the first restart, doubled for each quick successive exit
+    pub backoff_initial_ms: u64,
+    /// Upper bound on the restart delay
+    pub backoff_max_ms: u64,
+    /// Give up after this many exits within `crash_loop_window_secs`
+    pub crash_loop_exits: usize,
+    pub crash_loop_window_secs: u64,
+    /// Time walletshield is given to exit after `SIGTERM` before it is killed
+    pub shutdown_grace_ms: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            backoff_initial_ms: 500,
+            backoff_max_ms: 30_000,
+            crash_loop_exits: 5,
+            crash_loop_window_secs: 60,
+            shutdown_grace_ms: 5_000,
+        }
+    }
+}
+
+/// Capture of walletshield output in `dir_logs()`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LogConfig {
+    /// Size at which a log file is rotated
+    pub max_file_bytes: u64,
+    /// Number of log files kept per network, including the current one
+    pub max_files: usize,
+    /// Number of recent lines kept in memory
+    pub buffer_lines: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            max_file_bytes: 5 * 1024 * 1024,
+            max_files: 5,
+            buffer_lines: 1000,
+        }
+    }
+}
+
+/// Choice of walletshield's listen port.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ListenConfig {
+    /// Use a free port from the range below if the configured one is busy,
+    /// instead of failing
+    pub fallback: bool,
+    pub fallback_port_min: u16,
+    pub fallback_port_max: u16,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            fallback: false,
+            fallback_port_min: 7071,
+            fallback_port_max: 7099,
+        }
+    }
+}
+
+/// Single local endpoint routing chain requests to the connected networks.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RouterConfig {
+    /// Off unless enabled, as the router serves any local program
+    pub enabled: bool,
+    pub listen_address: String,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_address: "127.0.0.1:7100".into(),
+        }
+    }
+}
+
+/// Resource limits of walletshield, applied on Linux only.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ResourceLimits {
+    /// Cap on the virtual address space in bytes (`0` = unlimited)
+    pub max_address_space_bytes: u64,
+    /// Cap on open file descriptors, at most the inherited hard limit
+    /// (`0` = inherited)
+    pub max_open_files: u64,
+    /// Allow core dumps, which may contain key material
+    pub core_dumps: bool,
+    /// Niceness of walletshield relative to the client's, clamped to the
+    /// range -20..=19; a negative value requires privileges
+    pub nice: i32,
+    /// cgroup v2 under `/sys/fs/cgroup` in which each network gets a cgroup
+    /// of its own, e.g. a delegated `user.slice/.../zknet.slice`
+    pub cgroup: Option<String>,
+    /// `memory.max` of the network's cgroup (`0` = unlimited)
+    pub cgroup_memory_max_bytes: u64,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_address_space_bytes: 0,
+            max_open_files: 4096,
+            core_dumps: false,
+            nice: 0,
+            cgroup: None,
+            cgroup_memory_max_bytes: 0,
+        }
+    }
+}
+
+/// Resource limits of walletshield, with overrides per network.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LimitsConfig {
+    #[serde(flatten)]
+    pub defaults: ResourceLimits,
+    /// Limits by network ID, overriding the defaults field by field
+    pub networks: BTreeMap<String, Value>,
+}
+
+impl LimitsConfig {
+    /// The limits of a network's walletshield.
+    pub fn for_network(&self, network_id: &str) -> Result<ResourceLimits> {
+        let Some(overrides) = self.networks.get(network_id) else {
+            return Ok(self.defaults.clone());
+        };
+        let merged = merge(serde_json::to_value(&self.defaults)?, overrides.clone());
+        serde_json::from_value(merged)
+            .with_context(|| format!("invalid limits.networks.{network_id}"))
+    }
+}
+
+/// Sandbox of walletshield, on Linux only.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SandboxConfig {
+    pub enabled: bool,
+    /// Further paths walletshield may read, e.g. a custom CA bundle
+    pub allow_read: Vec<String>,
 }
 
 pub fn load_config(paths: &AppPaths, config_json: &str) -> AppConfig {
@@ -38,3 +250,35 @@ fn merge(base: Value, override_: Value) -> Value {
         (_, override_leaf) => override_leaf,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_limits_override_defaults() {
+        let limits: LimitsConfig = serde_json::from_str(
+            r#"{
+                "maxOpenFiles": 1024,
+                "nice": 5,
+                "networks": {
+                    "net": { "nice": 10, "cgroup": "zknet.slice" },
+                    "bad": { "nice": "high" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let other = limits.for_network("other").unwrap();
+        assert_eq!(other.max_open_files, 1024);
+        assert_eq!(other.nice, 5);
+        assert!(!other.core_dumps);
+
+        let net = limits.for_network("net").unwrap();
+        assert_eq!(net.max_open_files, 1024);
+        assert_eq!(net.nice, 10);
+        assert_eq!(net.cgroup.as_deref(), Some("zknet.slice"));
+
+        assert!(limits.for_network("bad").is_err());
+    }
+}
@@ -3,20 +3,42 @@ use crate::{
     paths::AppPaths,
 };
 
+#[derive(Clone)]
 pub struct AppContext {
     pub config: AppConfig,
     pub paths: AppPaths,
     pub platform_arch: String,
+    /// Version of the app, which networks may require to lie within a range
+    pub client_version: String,
 }
 
 impl AppContext {
-    pub fn new(app_name: &str, config_json: &str, platform_arch: String) -> Self {
-        let paths = AppPaths::new(app_name);
+    pub fn new(
+        app_name: &str,
+        client_version: &str,
+        config_json: &str,
+        platform_arch: String,
+    ) -> Self {
+        Self::from_paths(
+            AppPaths::new(app_name),
+            client_version,
+            config_json,
+            platform_arch,
+        )
+    }
+
+    pub fn from_paths(
+        paths: AppPaths,
+        client_version: &str,
+        config_json: &str,
+        platform_arch: String,
+    ) -> Self {
         let config = load_config(&paths, config_json);
         Self {
             config,
             paths,
             platform_arch,
+            client_version: client_version.to_owned(),
         }
     }
 }
@@ -0,0 +1,166 @@
+//! Lifecycle events of a network's client.
+//!
+//! Events are broadcast to any number of subscribers, such as the CLI's
+//! output, the GUI and clients of the local API. A subscriber that falls
+//! behind misses events instead of holding up the client.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{
+    logs::LogLine,
+    net::{ProgressCallback, ProgressPayload},
+};
+
+/// Number of events kept for subscribers that fall behind.
+const CAPACITY: usize = 256;
+
+/// Download progress is reported in steps of this many percent.
+const PROGRESS_STEP: u64 = 5;
+
+/// Download progress of unknown size is reported every this many bytes.
+const PROGRESS_STEP_BYTES: u64 = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum LifecycleEvent {
+    /// Checking the network's compatibility and updates
+    Resolving,
+    Downloading {
+        asset: String,
+        /// Bytes downloaded so far
+        bytes: u64,
+        /// Percentage downloaded, if the size is known
+        progress: Option<u8>,
+    },
+    /// Checking downloaded or installed files
+    Verifying,
+    /// Recording the installed assets
+    Installing,
+    /// Spawning walletshield
+    Starting,
+    Ready,
+    /// Was ready, but lost the gateway connection or stopped listening
+    Degraded,
+    /// Walletshield exited, e.g. with `exit status: 1`
+    Exited {
+        status: String,
+    },
+    Error {
+        message: String,
+    },
+    /// A line of walletshield output
+    Output(LogLine),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkEvent {
+    pub network_id: String,
+    #[serde(flatten)]
+    pub event: LifecycleEvent,
+}
+
+/// Broadcasts the lifecycle events of a network's client.
+#[derive(Clone)]
+pub struct Events {
+    network_id: Arc<str>,
+    tx: broadcast::Sender<NetworkEvent>,
+}
+
+impl Events {
+    pub fn new(network_id: &str) -> Self {
+        Self {
+            network_id: Arc::from(network_id),
+            tx: broadcast::Sender::new(CAPACITY),
+        }
+    }
+
+    pub fn network_id(&self) -> &str {
+        &self.network_id
+    }
+
+    /// Receive the events emitted from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<NetworkEvent> {
+        self.tx.subscribe()
+    }
+
+    pub fn emit(&self, event: LifecycleEvent) {
+        // without subscribers, the event is simply dropped
+        let _ = self.tx.send(NetworkEvent {
+            network_id: self.network_id.to_string(),
+            event,
+        });
+    }
+
+    /// A download progress callback emitting `Downloading` events for `asset`.
+    pub(crate) fn download_progress(&self, asset: &str) -> ProgressCallback {
+        let (events, asset) = (self.clone(), asset.to_owned());
+        let mut reported = None;
+        Box::new(move |p: ProgressPayload| {
+            let (step, progress) = match (p.progress_total * 100).checked_div(p.total) {
+                Some(percent) => {
+                    let percent = percent.min(100);
+                    (percent / PROGRESS_STEP, Some(percent as u8))
+                }
+                None => (p.progress_total / PROGRESS_STEP_BYTES, None),
+            };
+            if reported != Some(step) {
+                reported = Some(step);
+                events.emit(LifecycleEvent::Downloading {
+                    asset: asset.clone(),
+                    bytes: p.progress_total,
+                    progress,
+                });
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_download_progress_is_throttled() {
+        let events = Events::new("net");
+        let mut rx = events.subscribe();
+        let mut progress = events.download_progress("walletshield");
+        for n in 1..=1000 {
+            progress(ProgressPayload {
+                progress: 1,
+                progress_total: n,
+                total: 1000,
+                transfer_speed: 0.0,
+            });
+        }
+
+        let mut percents = Vec::new();
+        while let Ok(e) = rx.try_recv() {
+            assert_eq!(e.network_id, "net");
+            match e.event {
+                LifecycleEvent::Downloading { progress, .. } => percents.push(progress.unwrap()),
+                e => panic!("unexpected event {e:?}"),
+            }
+        }
+        assert_eq!(percents.len(), 21);
+        assert_eq!(percents.first(), Some(&0));
+        assert_eq!(percents.last(), Some(&100));
+    }
+
+    #[test]
+    fn test_serializes_flat() {
+        let event = NetworkEvent {
+            network_id: "net".into(),
+            event: LifecycleEvent::Exited {
+                status: "exit status: 1".into(),
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"networkId":"net","kind":"exited","status":"exit status: 1"}"#
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Garbage collection of the networks directory.
+//!
+//! Removes networks that have not been used for a while, orphaned staging
+//! files left behind by interrupted downloads, and blobs in the asset store
+//! that no installed network references anymore (e.g. old versions).
+//!
+//! Networks with a running session are never removed.
+
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    config::GcConfig,
+    lock::{running_session, AlreadyRunning},
+    registry::{NetworkInfo, NetworkRegistry},
+    utils::unix_now,
+};
+
+/// Staging files younger than this may belong to a download in progress.
+const STAGING_MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcReport {
+    pub removed_networks: Vec<String>,
+    pub removed_staging: Vec<PathBuf>,
+    pub removed_blobs: Vec<String>,
+}
+
+/// Collect garbage according to `cfg`, never removing the networks in `keep`.
+pub fn collect_garbage(
+    registry: &NetworkRegistry,
+    cfg: &GcConfig,
+    keep: &[&str],
+) -> Result<GcReport> {
+    let mut report = GcReport::default();
+    let networks = registry.list()?;
+
+    for n in &networks {
+        report
+            .removed_staging
+            .extend(remove_staging(registry, &n.meta.id)?);
+    }
+
+    // least recently used first
+    let mut candidates = Vec::new();
+    for n in &networks {
+        let id = n.meta.id.as_str();
+        if !keep.contains(&id) && running_session(registry.paths(), id)?.is_none() {
+            candidates.push((last_used(registry, n), n));
+        }
+    }
+    candidates.sort_by_key(|(last_used, _)| *last_used);
+
+    let now = unix_now();
+    let max_idle = cfg.max_idle_days * 24 * 60 * 60;
+    let mut total: u64 = networks.iter().map(|n| n.size_on_disk).sum();
+
+    for (last_used, n) in candidates {
+        let idle = cfg.max_idle_days > 0 && now.saturating_sub(last_used) > max_idle;
+        let over_cap = cfg.max_total_bytes > 0 && total > cfg.max_total_bytes;
+        if idle || over_cap {
+            match registry.remove(&n.meta.id) {
+                // started since it was listed
+                Err(e) if e.is::<AlreadyRunning>() => continue,
+                result => result?,
+            }
+            total = total.saturating_sub(n.size_on_disk);
+            report.removed_networks.push(n.meta.id.clone());
+        }
+    }
+
+    report.removed_blobs = registry.store().gc()?;
+    Ok(report)
+}
+
+/// Unix timestamp of the last connect or install of a network.
+///
+/// Networks without metadata fall back to the modification time of their
+/// directory, or to now if even that is unknown, so that they are never
+/// mistaken for networks unused since 1970.
+fn last_used(registry: &NetworkRegistry, n: &NetworkInfo) -> u64 {
+    n.meta
+        .last_connected
+        .or(n.meta.installed_at)
+        .or_else(|| {
+            let modified = fs::metadata(registry.dir_network(&n.meta.id))
+                .and_then(|m| m.modified())
+                .ok()?;
+            Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+        })
+        .unwrap_or_else(unix_now)
+}
+
+/// Remove stale partial downloads and temporary files of a network.
+fn remove_staging(registry: &NetworkRegistry, network_id: &str) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for entry in fs::read_dir(registry.dir_network(network_id))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !(name.ends_with(".part") || name.ends_with(".tmp")) {
+            continue;
+        }
+
+        let modified = entry.metadata()?.modified()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age < STAGING_MIN_AGE {
+            continue;
+        }
+
+        // archives are extracted into temporary directories
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+        removed.push(entry.path());
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lock::SessionLock, paths::AppPaths};
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn install(registry: &NetworkRegistry, id: &str, size: usize, last_connected: u64) {
+        fs::create_dir_all(registry.dir_network(id)).unwrap();
+        fs::write(registry.dir_network(id).join("client.toml"), vec![0; size]).unwrap();
+        let mut meta = registry.load(id).unwrap();
+        meta.last_connected = Some(last_connected);
+        registry.save(&meta).unwrap();
+    }
+
+    #[test]
+    fn test_removes_idle_networks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let registry = NetworkRegistry::new(&AppPaths::from_dir(tmp.path().to_path_buf()));
+        let now = unix_now();
+        install(&registry, "idle", 1, now - 100 * DAY);
+        install(&registry, "idle-but-kept", 1, now - 100 * DAY);
+        install(&registry, "recent", 1, now - DAY);
+
+        let cfg = GcConfig::default();
+        let report = collect_garbage(&registry, &cfg, &["idle-but-kept"]).unwrap();
+        assert_eq!(report.removed_networks, vec!["idle"]);
+        assert_eq!(registry.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_keeps_running_and_legacy_networks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths = AppPaths::from_dir(tmp.path().to_path_buf());
+        let registry = NetworkRegistry::new(&paths);
+        install(&registry, "running", 1, unix_now() - 100 * DAY);
+        // installed by an older client, without metadata
+        fs::create_dir_all(registry.dir_network("legacy")).unwrap();
+        fs::write(registry.dir_network("legacy").join("client.toml"), "x").unwrap();
+
+        let _lock = SessionLock::acquire(&paths, "running", "127.0.0.1:0").unwrap();
+        let report = collect_garbage(&registry, &GcConfig::default(), &[]).unwrap();
+        assert!(report.removed_networks.is_empty());
+        assert_eq!(registry.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_enforces_size_cap_lru_first() {
+        let tmp = tempfile::tempdir().unwrap();
+        let registry = NetworkRegistry::new(&AppPaths::from_dir(tmp.path().to_path_buf()));
+        let now = unix_now();
+        install(&registry, "a", 1000, now - 3 * DAY);
+        install(&registry, "b", 1000, now - 2 * DAY);
+        install(&registry, "c", 1000, now - DAY);
+
+        let meta_size = registry.inspect("a").unwrap().size_on_disk - 1000;
+        let cfg = GcConfig {
+            max_idle_days: 0,
+            max_total_bytes: 2000 + 3 * meta_size,
+            after_connect: true,
+        };
+        let report = collect_garbage(&registry, &cfg, &[]).unwrap();
+        assert_eq!(report.removed_networks, vec!["a"]);
+    }
+
+    #[test]
+    fn test_removes_old_staging_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let registry = NetworkRegistry::new(&AppPaths::from_dir(tmp.path().to_path_buf()));
+        install(&registry, "net", 1, unix_now());
+
+        let dir = registry.dir_network("net");
+        let old = dir.join("walletshield.part");
+        let fresh = dir.join("client.toml.part");
+        fs::write(&old, "x").unwrap();
+        fs::write(&fresh, "x").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * STAGING_MIN_AGE)
+            .unwrap();
+
+        let report = collect_garbage(&registry, &GcConfig::default(), &[]).unwrap();
+        assert_eq!(report.removed_staging, vec![old]);
+        assert!(fresh.exists());
+    }
+}
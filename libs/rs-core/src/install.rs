@@ -0,0 +1,231 @@
+//! Installation of staged assets into a network directory.
+//!
+//! Assets are first written to a staging location (a `.part` download or an
+//! extracted archive/bundle) and only then moved into the network directory,
+//! with binaries going through the shared blob store.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    archive::{self, ArchiveKind},
+    binfmt::check_binary,
+    process::FILE_PID,
+    registry::{AssetRecord, FILE_META},
+    store::{hash_file, BlobStore},
+};
+
+/// Assets downloaded on their own, which the files of an archived binary
+/// must not replace.
+const STANDALONE_ASSETS: &[&str] = &["client.toml", "services.json"];
+
+#[derive(Clone)]
+pub(crate) struct Installer {
+    pub dir: Arc<PathBuf>,
+    pub platform_arch: Arc<String>,
+    pub store: Arc<BlobStore>,
+}
+
+impl Installer {
+    /// Install the staged file of the asset `name`.
+    ///
+    /// A binary asset may be an `.tar.gz` or `.zip` archive holding the binary
+    /// along with extra files, which are then all installed.
+    pub async fn install_staged(
+        &self,
+        name: &str,
+        is_binary: bool,
+        path_staged: &Path,
+    ) -> Result<Vec<AssetRecord>> {
+        let path = self.dir.join(name);
+
+        if !is_binary {
+            tokio::fs::rename(path_staged, &path).await?;
+            return Ok(vec![self.record(&path).await?]);
+        }
+
+        let path_sniff = path_staged.to_path_buf();
+        match tokio::task::spawn_blocking(move || archive::sniff(&path_sniff)).await?? {
+            Some(kind) => self.install_archive(name, path_staged, kind).await,
+            None => {
+                // if platform is windows, the binary needs the .exe extension
+                let path = if self.platform() == "windows" {
+                    path.with_extension("exe")
+                } else {
+                    path
+                };
+                Ok(vec![self.install_binary(path_staged, &path).await?])
+            }
+        }
+    }
+
+    /// Extract an archived asset and install its files.
+    async fn install_archive(
+        &self,
+        name: &str,
+        path_archive: &Path,
+        kind: ArchiveKind,
+    ) -> Result<Vec<AssetRecord>> {
+        println!("  == extracting {kind:?} archive");
+        let dir_extract = self.dir.join(format!("{name}.extract.tmp"));
+        if dir_extract.exists() {
+            tokio::fs::remove_dir_all(&dir_extract).await?;
+        }
+
+        let (src, dest) = (path_archive.to_path_buf(), dir_extract.clone());
+        let files =
+            tokio::task::spawn_blocking(move || archive::extract(&src, kind, &dest)).await??;
+        tokio::fs::remove_file(path_archive).await?;
+
+        for rel in &files {
+            ensure!(
+                !STANDALONE_ASSETS.iter().any(|n| Path::new(n) == rel),
+                "archive {name} must not contain {}",
+                rel.display()
+            );
+        }
+        let records = self.install_tree(&dir_extract, &files, name).await?;
+        tokio::fs::remove_dir_all(&dir_extract).await?;
+        Ok(records)
+    }
+
+    /// Move the `files` (relative to `dir_staging`) into the network directory.
+    ///
+    /// The binary `name_binary` (or its `.exe` variant) must be among them.
+    pub async fn install_tree(
+        &self,
+        dir_staging: &Path,
+        files: &[PathBuf],
+        name_binary: &str,
+    ) -> Result<Vec<AssetRecord>> {
+        let names_binary = [name_binary.to_owned(), format!("{name_binary}.exe")];
+        for rel in files {
+            check_not_reserved(rel, &names_binary)?;
+        }
+
+        let mut records = Vec::new();
+        for rel in files {
+            let (src, dest) = (dir_staging.join(rel), self.dir.join(rel));
+            if let Some(parent) = dest.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            if names_binary.iter().any(|n| Path::new(n) == rel) {
+                records.push(self.install_binary(&src, &dest).await?);
+            } else {
+                if tokio::fs::symlink_metadata(&dest).await.is_ok() {
+                    tokio::fs::remove_file(&dest).await?;
+                }
+                tokio::fs::rename(&src, &dest).await?;
+                records.push(self.record(&dest).await?);
+            }
+        }
+
+        ensure!(
+            records.iter().any(|r| names_binary.contains(&r.name)),
+            "assets do not contain the {name_binary} binary"
+        );
+        Ok(records)
+    }
+
+    /// Make a binary executable and install it via the blob store.
+    ///
+    /// The binary must be built for this platform.
+    async fn install_binary(&self, src: &Path, dest: &Path) -> Result<AssetRecord> {
+        let (path_check, platform_arch) = (src.to_path_buf(), self.platform_arch.clone());
+        let info = tokio::task::spawn_blocking(move || check_binary(&path_check, &platform_arch))
+            .await??;
+        println!("  == verified {info}");
+
+        let platform = self.platform();
+
+        // if platform is unix, set the file permissions to 755
+        if platform == "linux" || platform == "macos" {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mut perms = tokio::fs::metadata(src).await?.permissions();
+                perms.set_mode(0o755); // rwxr-xr-x
+                tokio::fs::set_permissions(src, perms).await?;
+            }
+        }
+
+        let size = tokio::fs::metadata(src).await?.len();
+
+        // binaries are often identical across networks, so share them
+        let store = self.store.clone();
+        let (src, dest_blob) = (src.to_path_buf(), dest.to_path_buf());
+        let (sha256, kind) =
+            tokio::task::spawn_blocking(move || store.adopt(&src, &dest_blob)).await??;
+        println!("  == blob {sha256} ({kind})");
+
+        Ok(AssetRecord {
+            name: self.asset_name(dest),
+            sha256,
+            size,
+        })
+    }
+
+    /// Hash an installed asset that is not stored as a blob.
+    async fn record(&self, path: &Path) -> Result<AssetRecord> {
+        let size = tokio::fs::metadata(path).await?.len();
+        let path_hash = path.to_path_buf();
+        let sha256 = tokio::task::spawn_blocking(move || hash_file(&path_hash)).await??;
+        Ok(AssetRecord {
+            name: self.asset_name(path),
+            sha256,
+            size,
+        })
+    }
+
+    /// Name of an asset relative to the network directory, `/`-separated.
+    fn asset_name(&self, path: &Path) -> String {
+        let rel = path.strip_prefix(&*self.dir).unwrap_or(path);
+        rel.components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn platform(&self) -> &str {
+        self.platform_arch.split('-').next().unwrap_or("")
+    }
+}
+
+/// Path of the asset `name` relative to the network directory.
+///
+/// Fails if the name leaves the directory or is reserved, see
+/// `check_not_reserved`.
+pub(crate) fn asset_path(name: &str, name_binary: &str) -> Result<PathBuf> {
+    let rel = archive::normal_path(Path::new(name))
+        .with_context(|| format!("invalid asset name {name}"))?;
+    check_not_reserved(
+        &rel,
+        &[name_binary.to_owned(), format!("{name_binary}.exe")],
+    )?;
+    Ok(rel)
+}
+
+/// Ensure the asset `rel` does not replace a file the client writes itself,
+/// nor pass for the binary anywhere but in the binary slot `names_binary`.
+fn check_not_reserved(rel: &Path, names_binary: &[String]) -> Result<()> {
+    let name = rel
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    ensure!(
+        name != FILE_META && name != FILE_PID && !name.ends_with(".lock"),
+        "asset {} has a reserved name",
+        rel.display()
+    );
+    ensure!(
+        !names_binary.iter().any(|n| *n == name)
+            || names_binary.iter().any(|n| Path::new(n) == rel),
+        "asset {} is not in the place of the binary",
+        rel.display()
+    );
+    Ok(())
+}
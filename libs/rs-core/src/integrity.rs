@@ -0,0 +1,145 @@
+//! Tamper detection for installed walletshield binaries.
+//!
+//! Between connects the binary sits in the user's data directory, where other
+//! programs may replace or modify it. Before every launch its hash is compared
+//! with the one recorded at install time, and its ownership and permissions
+//! are checked so that no other user could have swapped it.
+
+use std::{error::Error, fmt, fs, path::Path};
+
+use anyhow::Result;
+
+use crate::{registry::NetworkRegistry, store::hash_file};
+
+/// The installed binary of a network no longer matches what was installed.
+///
+/// Reinstalling the network restores it.
+#[derive(Debug)]
+pub struct TamperError {
+    pub network_id: String,
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for TamperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "refusing to run the walletshield binary of network {}: {}; \
+             reinstall the network to restore it",
+            self.network_id,
+            self.problems.join(", ")
+        )
+    }
+}
+
+impl Error for TamperError {}
+
+/// Verify the installed binary at `path` against the network's metadata.
+pub fn verify_binary(registry: &NetworkRegistry, network_id: &str, path: &Path) -> Result<()> {
+    let meta = registry.load(network_id)?;
+    let dir = registry.dir_network(network_id);
+    let mut problems = Vec::new();
+
+    let name = path.strip_prefix(&dir).unwrap_or(path).to_string_lossy();
+    match meta.assets.iter().find(|a| a.name == name) {
+        Some(record) => {
+            if hash_file(path)? != record.sha256 {
+                problems.push(format!("{name} was modified since it was installed"));
+            }
+        }
+        None => problems.push(format!("no hash of {name} was recorded at install time")),
+    }
+
+    // the binary is installed with mode 755, while the directory follows the
+    // umask, which may grant write access to the user's private group
+    problems.extend(check_permissions(path, &fs::metadata(path)?, 0o022));
+    problems.extend(check_permissions(&dir, &fs::metadata(&dir)?, 0o002));
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(TamperError {
+            network_id: network_id.to_owned(),
+            problems,
+        }
+        .into())
+    }
+}
+
+/// Check that `path` is owned by the current user and has none of the
+/// `forbidden` permission bits.
+#[cfg(unix)]
+fn check_permissions(path: &Path, metadata: &fs::Metadata, forbidden: u32) -> Vec<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut problems = Vec::new();
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let uid = unsafe { libc::geteuid() };
+    if metadata.uid() != uid {
+        problems.push(format!(
+            "{} is owned by uid {} instead of {uid}",
+            path.display(),
+            metadata.uid()
+        ));
+    }
+    if metadata.mode() & forbidden != 0 {
+        problems.push(format!(
+            "{} is writable by other users (mode {:o})",
+            path.display(),
+            metadata.mode() & 0o7777
+        ));
+    }
+    problems
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path, _metadata: &fs::Metadata, _forbidden: u32) -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{paths::AppPaths, registry::AssetRecord};
+
+    #[test]
+    fn test_detects_modified_binary() {
+        let tmp = tempfile::tempdir().unwrap();
+        let registry = NetworkRegistry::new(&AppPaths::from_dir(tmp.path().to_path_buf()));
+        let dir = registry.dir_network("net");
+        let bin = dir.join("walletshield");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&bin, b"bin").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+            fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let record = AssetRecord {
+            name: "walletshield".into(),
+            sha256: hash_file(&bin).unwrap(),
+            size: 3,
+        };
+        registry
+            .record_install("net", "", vec![record], Default::default())
+            .unwrap();
+        verify_binary(&registry, "net", &bin).unwrap();
+
+        fs::write(&bin, b"evil").unwrap();
+        let err = verify_binary(&registry, "net", &bin).unwrap_err();
+        let err = err.downcast_ref::<TamperError>().unwrap();
+        assert_eq!(err.problems.len(), 1);
+        assert!(err.problems[0].contains("modified"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::write(&bin, b"bin").unwrap();
+            fs::set_permissions(&bin, fs::Permissions::from_mode(0o777)).unwrap();
+            let err = verify_binary(&registry, "net", &bin).unwrap_err();
+            assert!(err.to_string().contains("writable by other users"));
+        }
+    }
+}
@@ -1,78 +1,126 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-use crate::net::{download, ProgressCallback, ProgressPayload};
-use anyhow::{ensure, Result};
+use crate::{
+    compat::ensure_compatible,
+    context::AppContext,
+    events::{Events, LifecycleEvent},
+    gc::collect_garbage,
+    install::Installer,
+    integrity::verify_binary,
+    limits::{apply_limits, prepare_cgroup, remove_cgroup},
+    listen::ListenAddr,
+    logs::{NetworkLog, Stream},
+    net::download,
+    process::{bind_to_parent, reap_stale, remove_pid, write_pid},
+    readiness::{probe_listening, Readiness, ReadyState},
+    registry::{validate_network_id, AssetRecord, NetworkRegistry},
+    sandbox::{apply_sandbox, SandboxReport},
+    supervisor::{Shutdown, Supervisor},
+    updates::{check_updates, RemoteAsset},
+};
+use anyhow::Result;
 use reqwest::Client;
-use tokio::fs::File;
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Child,
+};
 
+pub mod archive;
+pub mod binfmt;
+pub mod bundle;
+pub mod catalog;
+pub mod client;
+pub mod compat;
 pub mod config;
 pub mod context;
+pub mod events;
+pub mod gc;
+mod install;
+pub mod integrity;
+pub mod limits;
+pub mod listen;
+pub mod lock;
+pub mod logparse;
+pub mod logs;
 pub mod net;
 pub mod paths;
+pub mod process;
+pub mod readiness;
+pub mod registry;
+pub mod router;
+pub mod sandbox;
+pub mod sessions;
+pub mod store;
+pub mod supervisor;
+pub mod updates;
 pub mod utils;
 
 #[derive(Clone)]
 struct DlCtx {
     client: Arc<Client>,
-    dir: Arc<PathBuf>,
     url_base: Arc<str>,
-    platform_arch: Arc<String>,
+    installer: Installer,
+    events: Events,
 }
 
 impl DlCtx {
-    async fn asset(&self, name: &str, is_binary: bool, show_progress: bool) -> Result<()> {
+    /// Download an asset next to its destination in the network directory.
+    ///
+    /// Returns the path of the staged download along with its version.
+    async fn download(&self, name: &str, is_binary: bool) -> Result<(PathBuf, RemoteAsset)> {
         let mut url = format!("{}/{name}", self.url_base);
         if is_binary {
-            url.push_str(&format!("-{}", self.platform_arch));
+            url.push_str(&format!("-{}", self.installer.platform_arch));
         }
-        let path = self.dir.join(name);
+        let path = self.installer.dir.join(name);
         println!("  << {url}\n  >> {}", path.display());
 
-        let mut file = File::create(&path).await?;
+        // download next to the destination so an existing file (which may be
+        // a hard link into the blob store) is never written through
+        let path_part = path_part(&self.installer.dir, name);
+        let mut file = File::create(&path_part).await?;
 
-        let progress = show_progress.then(|| {
-            Box::new(move |p: ProgressPayload| {
-                println!("Download progress: {}/{}", p.progress_total, p.total);
-            }) as ProgressCallback
-        });
-
-        download(&self.client, &url, &mut file, progress, None, None).await?;
-
-        if is_binary {
-            let platform = self.platform_arch.split('-').next().unwrap_or("");
-
-            // if platform is unix, set the file permissions to 755
-            if platform == "linux" || platform == "macos" {
-                #[cfg(unix)]
-                {
-                    use std::os::unix::fs::PermissionsExt;
-                    let mut perms = file.metadata().await?.permissions();
-                    perms.set_mode(0o755); // rwxr-xr-x
-                    tokio::fs::set_permissions(&path, perms).await?;
-                }
-            }
-
-            // if platform is windows, rename the file to .exe
-            if platform == "windows" {
-                let new_path = path.with_extension("exe");
-                tokio::fs::rename(&path, &new_path).await?;
-                println!("Renamed {} to {}", path.display(), new_path.display());
-            }
-        }
-
-        Ok::<(), anyhow::Error>(())
+        let progress = Some(self.events.download_progress(name));
+        let headers = download(&self.client, &url, &mut file, progress, None, None).await?;
+        Ok((path_part, RemoteAsset::from_headers(&url, &headers)))
     }
 }
 
-/// Start the client for the specified network from the downloaded assets.
-async fn start_network_client(ctx: crate::context::AppContext, network_id: &str) -> Result<()> {
-    let dir_network = ctx.paths.dir_data().join("networks").join(network_id);
+/// Path of the staged download of an asset.
+fn path_part(dir_network: &Path, name: &str) -> PathBuf {
+    dir_network.join(format!("{name}.part"))
+}
 
+/// Path of the walletshield binary of a network.
+fn path_walletshield(ctx: &AppContext, network_id: &str) -> PathBuf {
     let platform = ctx.platform_arch.split('-').next().unwrap_or("");
-    let mut path_walletshield = dir_network.join("walletshield");
+    let mut path = ctx
+        .paths
+        .dir_networks()
+        .join(network_id)
+        .join("walletshield");
     if platform == "windows" {
-        path_walletshield.set_extension("exe");
+        path.set_extension("exe");
     }
+    path
+}
+
+/// Spawn the client for the specified network from the downloaded assets.
+async fn spawn_network_client(
+    ctx: &AppContext,
+    network_id: &str,
+    readiness: &Arc<Readiness>,
+    log: &Arc<NetworkLog>,
+    events: &Events,
+    sandbox: &Mutex<Option<SandboxReport>>,
+) -> Result<Child> {
+    let dir_network = ctx.paths.dir_networks().join(network_id);
+    let path_walletshield = path_walletshield(ctx, network_id);
 
     // ensure the walletshield binary exists
     if !path_walletshield.exists() {
@@ -82,111 +130,391 @@ async fn start_network_client(ctx: crate::context::AppContext, network_id: &str)
         ));
     }
 
+    // refuse to run a binary that changed since it was installed
+    let registry = NetworkRegistry::new(&ctx.paths);
+    let (id, path) = (network_id.to_owned(), path_walletshield.clone());
+    tokio::task::spawn_blocking(move || verify_binary(&registry, &id, &path)).await??;
+
+    // a walletshield left behind by a crashed client would hold the port
+    let (dir, path) = (dir_network.clone(), path_walletshield.clone());
+    tokio::task::spawn_blocking(move || reap_stale(&dir, &path)).await??;
+
     // spawn the walletshield process
-    let mut command = tokio::process::Command::new(path_walletshield);
+    let mut command = tokio::process::Command::new(&path_walletshield);
     command.current_dir(&dir_network);
     command.stdout(std::process::Stdio::piped());
     command.stderr(std::process::Stdio::piped());
+    bind_to_parent(&mut command);
+    let limits = ctx.config.limits.for_network(network_id)?;
+    let cgroup = prepare_cgroup(&limits, network_id)?;
+    apply_limits(&mut command, &limits, cgroup)?;
+    let pending_sandbox = apply_sandbox(
+        &mut command,
+        &ctx.config.sandbox,
+        &dir_network,
+        &path_walletshield,
+    )?;
 
     command.arg("-listen");
-    command.arg(ctx.config.walletshield_listen_address);
+    command.arg(&ctx.config.walletshield_listen_address);
     command.arg("-config").arg("client.toml");
 
     println!("Starting network client...");
+    events.emit(LifecycleEvent::Starting);
+    readiness.reset();
     let mut child = command.spawn()?;
+    if let Some(pid) = child.id() {
+        write_pid(&dir_network, pid)?;
+    }
+    let applied = match pending_sandbox.read() {
+        Ok(applied) => applied,
+        Err(e) => {
+            let _ = child.start_kill();
+            return Err(e);
+        }
+    };
+    if ctx.config.sandbox.enabled {
+        println!("Sandbox: {applied}");
+    }
+    *sandbox.lock().unwrap() = Some(applied);
 
-    // Handle stdout
+    // Handle stdout and stderr
     if let Some(stdout) = child.stdout.take() {
-        let mut reader = tokio::io::BufReader::new(stdout);
-        tokio::spawn(async move {
-            let mut line = String::new();
-            loop {
-                line.clear();
-                match tokio::io::AsyncBufReadExt::read_line(&mut reader, &mut line).await {
-                    Ok(0) => break, // EOF
-                    Ok(_) => print!("{line}"),
-                    Err(e) => {
-                        eprintln!("Error reading stdout: {e}");
-                        break;
-                    }
-                }
-            }
-        });
+        tokio::spawn(pump_output(
+            stdout,
+            Stream::Stdout,
+            readiness.clone(),
+            log.clone(),
+            events.clone(),
+        ));
     }
-
-    // Handle stderr
     if let Some(stderr) = child.stderr.take() {
-        let mut reader = tokio::io::BufReader::new(stderr);
-        tokio::spawn(async move {
-            let mut line = String::new();
-            loop {
-                line.clear();
-                match tokio::io::AsyncBufReadExt::read_line(&mut reader, &mut line).await {
-                    Ok(0) => break, // EOF
-                    Ok(_) => eprint!("{line}"),
-                    Err(e) => {
-                        eprintln!("Error reading stderr: {e}");
-                        break;
-                    }
+        tokio::spawn(pump_output(
+            stderr,
+            Stream::Stderr,
+            readiness.clone(),
+            log.clone(),
+            events.clone(),
+        ));
+    }
+
+    Ok(child)
+}
+
+/// Forward the client's output to the terminal, its log, `readiness` and
+/// `events`.
+async fn pump_output(
+    output: impl AsyncRead + Unpin,
+    stream: Stream,
+    readiness: Arc<Readiness>,
+    log: Arc<NetworkLog>,
+    events: Events,
+) {
+    let mut reader = BufReader::new(output);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line).await {
+            Ok(0) => break, // EOF
+            Ok(_) => {
+                let entry = log.append(stream, &line);
+                if let Some(event) = &entry.event {
+                    readiness.observe_event(event);
+                }
+                match stream {
+                    Stream::Stdout => print!("{line}"),
+                    Stream::Stderr => eprint!("{line}"),
                 }
+                events.emit(LifecycleEvent::Output(entry));
             }
-        });
+            Err(e) => {
+                eprintln!("Error reading {stream:?}: {e}");
+                break;
+            }
+        }
     }
+}
 
-    // Wait for the process to finish (will run until killed)
-    let status = child.wait().await?;
-    println!("Client for network {network_id} exited with status: {status}");
+/// Emit `Ready` and `Degraded` events as the readiness changes.
+async fn emit_readiness(readiness: Arc<Readiness>, events: Events) {
+    let mut states = readiness.subscribe();
+    while states.changed().await.is_ok() {
+        let state = *states.borrow_and_update();
+        match state {
+            ReadyState::Ready => events.emit(LifecycleEvent::Ready),
+            ReadyState::Degraded => events.emit(LifecycleEvent::Degraded),
+            _ => {}
+        }
+    }
+}
 
-    Ok(())
+/// Collect garbage once the client is ready for the first time.
+///
+/// Garbage collection is housekeeping, which must neither delay nor prevent
+/// connecting, so it only warns when it fails.
+async fn collect_garbage_when_ready(
+    ctx: AppContext,
+    network_id: String,
+    readiness: Arc<Readiness>,
+) {
+    let mut states = readiness.subscribe();
+    if states.wait_for(|s| *s == ReadyState::Ready).await.is_err() {
+        return;
+    }
+    let registry = NetworkRegistry::new(&ctx.paths);
+    let cfg = ctx.config.gc;
+    let gc = tokio::task::spawn_blocking(move || collect_garbage(&registry, &cfg, &[&network_id]));
+    match gc.await.map_err(anyhow::Error::from).and_then(|r| r) {
+        Ok(report) if !report.removed_networks.is_empty() => {
+            println!("Removed unused networks: {:?}", report.removed_networks);
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Warning: failed to collect garbage: {e:#}"),
+    }
 }
 
-/// Connect to a network by downloading its assets and starting the client.
-pub async fn network_connect(ctx: crate::context::AppContext, network_id: &str) -> Result<()> {
-    println!("Connecting to network with ID={network_id}...");
+/// Run the client for the specified network under `supervisor` until it is
+/// stopped, tracking its readiness in `readiness`, its output in `log` and
+/// the protections of its sandbox in `sandbox`.
+async fn start_network_client(
+    ctx: AppContext,
+    network_id: &str,
+    supervisor: &Supervisor,
+    readiness: &Arc<Readiness>,
+    log: &Arc<NetworkLog>,
+    events: &Events,
+    sandbox: &Mutex<Option<SandboxReport>>,
+) -> Result<Shutdown> {
+    let addr = ListenAddr::parse(&ctx.config.walletshield_listen_address)?.probe;
+    let probe = tokio::spawn({
+        let readiness = readiness.clone();
+        async move { probe_listening(&readiness, addr).await }
+    });
+    let emit = tokio::spawn(emit_readiness(readiness.clone(), events.clone()));
+    let gc = ctx.config.gc.after_connect.then(|| {
+        tokio::spawn(collect_garbage_when_ready(
+            ctx.clone(),
+            network_id.to_owned(),
+            readiness.clone(),
+        ))
+    });
+
+    let result = supervisor
+        .run(|| spawn_network_client(&ctx, network_id, readiness, log, events, sandbox))
+        .await;
+    probe.abort();
+    emit.abort();
+    if let Some(gc) = gc {
+        gc.abort();
+    }
+    remove_pid(&ctx.paths.dir_networks().join(network_id))?;
+    let cgroup = ctx.config.limits.for_network(network_id);
+    if let Err(e) = cgroup.and_then(|limits| remove_cgroup(&limits, network_id)) {
+        eprintln!("Warning: {e:#}");
+    }
+    let shutdown = result?;
+    println!("Client for network {network_id} stopped ({shutdown:?})");
+
+    Ok(shutdown)
+}
 
+/// Install a network by downloading and verifying its assets, without
+/// starting its client.
+///
+/// Fails if the network does not support this client's version.
+pub async fn network_install(ctx: &AppContext, network_id: &str, events: &Events) -> Result<()> {
     // ensure network_id is safe
-    let path = std::path::Path::new(&network_id);
-    ensure!(
-        !path
-            .components()
-            .any(|c| !matches!(c, std::path::Component::Normal(_))),
-        "invalid network id: {path:?}"
-    );
+    validate_network_id(network_id)?;
+
+    events.emit(LifecycleEvent::Resolving);
+    ensure_compatible(
+        &ctx.paths,
+        &ctx.config.url_network,
+        network_id,
+        &ctx.client_version,
+    )
+    .await?;
+    install_network(ctx, network_id, events).await
+}
+
+/// Install a network known to be compatible.
+async fn install_network(ctx: &AppContext, network_id: &str, events: &Events) -> Result<()> {
+    println!("Installing network with ID={network_id}...");
 
     let client = Client::builder()
         .timeout(std::time::Duration::from_secs(10))
         .build()?;
 
     // create the directory for network assets, ensuring it exists
-    let dir_network = ctx.paths.dir_data().join("networks").join(network_id);
+    let registry = NetworkRegistry::new(&ctx.paths);
+    let dir_network = registry.dir_network(network_id);
     tokio::fs::create_dir_all(&dir_network).await?;
 
     let url_base = format!("{}/{}", ctx.config.url_network, network_id);
 
     let ctx_dl = DlCtx {
         client: Arc::new(client),
-        dir: Arc::new(dir_network),
-        url_base: Arc::from(url_base),
-        platform_arch: Arc::from(ctx.platform_arch.clone()),
+        url_base: Arc::from(url_base.as_str()),
+        installer: Installer {
+            dir: Arc::new(dir_network),
+            platform_arch: Arc::from(ctx.platform_arch.clone()),
+            store: registry.store(),
+        },
+        events: events.clone(),
     };
 
     println!("Downloading network assets...");
-    tokio::try_join!(
-        ctx_dl.asset("client.toml", false, false),
-        ctx_dl.asset("services.json", false, false),
-        ctx_dl.asset("walletshield", true, false),
-    )?;
+    let result = install_assets(&ctx_dl).await;
+    if result.is_err() {
+        // leave no partial download behind, whichever failed
+        for (name, _) in ASSETS {
+            let _ = tokio::fs::remove_file(path_part(&ctx_dl.installer.dir, name)).await;
+        }
+    }
+    let (assets, remote) = result?;
+    registry.record_install(network_id, &url_base, assets, remote)?;
 
-    start_network_client(ctx, network_id).await?;
+    Ok(())
+}
+
+/// The assets of a network, and whether each is a binary.
+const ASSETS: [(&str, bool); 3] = [
+    ("walletshield", true),
+    ("client.toml", false),
+    ("services.json", false),
+];
+
+/// Download all assets of a network, then install them together.
+///
+/// None is installed unless all were downloaded, and the binary, the only
+/// asset that can be refused, is installed first, so that a failed install
+/// never leaves a mix of old and new assets behind.
+async fn install_assets(
+    ctx_dl: &DlCtx,
+) -> Result<(Vec<AssetRecord>, BTreeMap<String, RemoteAsset>)> {
+    let staged =
+        futures_util::future::try_join_all(ASSETS.iter().map(|&(name, is_binary)| async move {
+            let (path, version) = ctx_dl.download(name, is_binary).await?;
+            anyhow::Ok((name, is_binary, path, version))
+        }))
+        .await?;
+
+    ctx_dl.events.emit(LifecycleEvent::Verifying);
+    let mut records = Vec::new();
+    let mut remote = BTreeMap::new();
+    for (name, is_binary, path, version) in staged {
+        records.extend(
+            ctx_dl
+                .installer
+                .install_staged(name, is_binary, &path)
+                .await?,
+        );
+        remote.insert(name.to_owned(), version);
+    }
+    ctx_dl.events.emit(LifecycleEvent::Installing);
+    Ok((records, remote))
+}
+
+/// Whether a network must be (re)installed before connecting to it.
+async fn needs_install(ctx: &AppContext, network_id: &str) -> bool {
+    if !NetworkRegistry::new(&ctx.paths).is_installed(network_id) {
+        return true;
+    }
+
+    match check_updates(&ctx.paths, network_id).await {
+        Ok(updates) if updates.is_empty() => false,
+        Ok(updates) => {
+            let names: Vec<_> = updates.iter().map(|u| u.name.as_str()).collect();
+            if ctx.config.updates.apply_on_connect {
+                println!("Updating network assets: {names:?}");
+                true
+            } else {
+                println!("Updates available for {names:?}; run `update --apply` to install them");
+                false
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to check for updates, using installed assets: {e:#}");
+            false
+        }
+    }
+}
+
+/// Prepare a network for starting its client: install or update its assets
+/// as needed and check the integrity of its binary.
+async fn prepare_network(ctx: &AppContext, network_id: &str, events: &Events) -> Result<()> {
+    println!("Connecting to network with ID={network_id}...");
+
+    // ensure network_id is safe
+    validate_network_id(network_id)?;
+
+    events.emit(LifecycleEvent::Resolving);
+    ensure_compatible(
+        &ctx.paths,
+        &ctx.config.url_network,
+        network_id,
+        &ctx.client_version,
+    )
+    .await?;
+
+    if needs_install(ctx, network_id).await {
+        install_network(ctx, network_id, events).await?;
+    }
+
+    let registry = NetworkRegistry::new(&ctx.paths);
+    registry.record_connected(network_id)?;
+
+    // report a tampered binary to the caller of `start` rather than as a
+    // failure of the running client
+    events.emit(LifecycleEvent::Verifying);
+    let (id, path) = (network_id.to_owned(), path_walletshield(ctx, network_id));
+    tokio::task::spawn_blocking(move || verify_binary(&registry, &id, &path)).await??;
 
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::{config::LogConfig, logparse::ClientEvent, paths::AppPaths};
+
     #[test]
     fn it_works() {
         let result = 2 + 2;
         assert_eq!(result, 4);
     }
+
+    #[tokio::test]
+    async fn test_output_is_emitted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths = AppPaths::from_dir(tmp.path().to_path_buf());
+        let log = Arc::new(NetworkLog::open(&paths, "net", LogConfig::default()).unwrap());
+        let readiness = Arc::new(Readiness::new());
+        let events = Events::new("net");
+        let mut rx = events.subscribe();
+
+        let output = "starting\n15:04:05.000 INFO client2/conn: Connected to gateway gw1\n";
+        pump_output(
+            output.as_bytes(),
+            Stream::Stderr,
+            readiness.clone(),
+            log,
+            events,
+        )
+        .await;
+
+        let mut lines = Vec::new();
+        while let Ok(e) = rx.try_recv() {
+            match e.event {
+                LifecycleEvent::Output(line) => lines.push(line),
+                e => panic!("unexpected event {e:?}"),
+            }
+        }
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].line, "starting");
+        assert_eq!(lines[0].stream, Stream::Stderr);
+        assert_eq!(lines[0].event, None);
+        assert_eq!(lines[1].event, Some(ClientEvent::GatewayConnected));
+        assert_eq!(readiness.state(), ReadyState::ConnectingToGateway);
+    }
 }
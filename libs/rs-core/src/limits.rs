@@ -0,0 +1,222 @@
+//! Resource limits of the walletshield process.
+//!
+//! A misbehaving walletshield must not exhaust the memory, file descriptors
+//! or CPU of the machine it runs on. On Linux, its rlimits and niceness
+//! (relative to the client's) are set between fork and exec, and it can be
+//! placed in a cgroup v2 of its own before it runs a single instruction of its
+//! binary. Elsewhere the limits are ignored.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use tokio::process::Command;
+
+use crate::config::ResourceLimits;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(all(target_os = "linux", not(target_env = "gnu")))]
+type Resource = libc::c_int;
+
+/// Root of the cgroup v2 hierarchy.
+#[cfg(target_os = "linux")]
+const DIR_CGROUP: &str = "/sys/fs/cgroup";
+
+/// Parent directory and directory of the cgroup of a network, if configured.
+#[cfg(target_os = "linux")]
+fn dir_cgroup(limits: &ResourceLimits, network_id: &str) -> Option<(PathBuf, PathBuf)> {
+    let parent =
+        std::path::Path::new(DIR_CGROUP).join(limits.cgroup.as_ref()?.trim_start_matches('/'));
+    let dir = parent.join(format!("zknet-{network_id}"));
+    Some((parent, dir))
+}
+
+/// Create the cgroup of a network under the configured one, returning its
+/// directory.
+///
+/// The cgroup is reused by every walletshield the session spawns, and removed
+/// with `remove_cgroup` once the session ends.
+#[cfg(target_os = "linux")]
+pub fn prepare_cgroup(limits: &ResourceLimits, network_id: &str) -> Result<Option<PathBuf>> {
+    use std::fs;
+
+    use anyhow::Context;
+
+    let Some((parent, dir)) = dir_cgroup(limits, network_id) else {
+        return Ok(None);
+    };
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create cgroup {}", dir.display()))?;
+
+    if limits.cgroup_memory_max_bytes > 0 {
+        // the memory controller must be enabled in the parent for the
+        // network's cgroup to have a memory.max
+        let path = parent.join("cgroup.subtree_control");
+        fs::write(&path, "+memory")
+            .with_context(|| format!("failed to enable memory controller in {}", path.display()))?;
+        let path = dir.join("memory.max");
+        fs::write(&path, limits.cgroup_memory_max_bytes.to_string())
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(Some(dir))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn prepare_cgroup(_limits: &ResourceLimits, _network_id: &str) -> Result<Option<PathBuf>> {
+    Ok(None)
+}
+
+/// Remove the cgroup of a network once its walletshield exited, if any.
+#[cfg(target_os = "linux")]
+pub fn remove_cgroup(limits: &ResourceLimits, network_id: &str) -> Result<()> {
+    use std::{fs, io};
+
+    use anyhow::Context;
+
+    let Some((_, dir)) = dir_cgroup(limits, network_id) else {
+        return Ok(());
+    };
+    // a cgroup is removed with rmdir, which fails while it has processes
+    match fs::remove_dir(&dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("failed to remove cgroup {}", dir.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn remove_cgroup(_limits: &ResourceLimits, _network_id: &str) -> Result<()> {
+    Ok(())
+}
+
+/// Configure `command` to run with `limits`, and in `cgroup` if any.
+#[cfg(target_os = "linux")]
+pub fn apply_limits(
+    command: &mut Command,
+    limits: &ResourceLimits,
+    cgroup: Option<PathBuf>,
+) -> Result<()> {
+    use std::{ffi::CString, io, os::unix::ffi::OsStringExt};
+
+    // allocated before forking, as the child may not allocate
+    let cgroup_procs = cgroup
+        .map(|dir| CString::new(dir.join("cgroup.procs").into_os_string().into_vec()))
+        .transpose()?;
+    let nice = if limits.nice != 0 {
+        Some((current_nice()? + limits.nice).clamp(-20, 19))
+    } else {
+        None
+    };
+    let limits = limits.clone();
+
+    // SAFETY: only async-signal-safe functions are called between fork and
+    // exec
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(path) = &cgroup_procs {
+                // "0" moves the writing process itself
+                let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                libc::close(fd);
+                if written != 1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if limits.max_address_space_bytes > 0 {
+                set_rlimit(libc::RLIMIT_AS, limits.max_address_space_bytes)?;
+            }
+            if limits.max_open_files > 0 {
+                set_rlimit(libc::RLIMIT_NOFILE, limits.max_open_files)?;
+            }
+            if !limits.core_dumps {
+                set_rlimit(libc::RLIMIT_CORE, 0)?;
+            }
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply_limits(
+    _command: &mut Command,
+    _limits: &ResourceLimits,
+    _cgroup: Option<PathBuf>,
+) -> Result<()> {
+    Ok(())
+}
+
+/// Niceness of the calling process.
+#[cfg(target_os = "linux")]
+fn current_nice() -> std::io::Result<i32> {
+    // SAFETY: getpriority has no memory-safety preconditions, and errno is
+    // thread-local
+    unsafe {
+        // -1 is a valid niceness, so errors are told apart by errno
+        *libc::__errno_location() = 0;
+        let nice = libc::getpriority(libc::PRIO_PROCESS as _, 0);
+        if nice == -1 && *libc::__errno_location() != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(nice)
+    }
+}
+
+/// Set both limits of `resource` to `value`, capped at the current hard limit
+/// which an unprivileged process cannot raise.
+///
+/// Async-signal-safe.
+#[cfg(target_os = "linux")]
+fn set_rlimit(resource: Resource, value: u64) -> std::io::Result<()> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit and setrlimit only access `limit`
+    unsafe {
+        if libc::getrlimit(resource, &mut limit) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let value = (value as libc::rlim_t).min(limit.rlim_max);
+        limit.rlim_cur = value;
+        limit.rlim_max = value;
+        if libc::setrlimit(resource, &limit) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limits_apply_to_child() {
+        let limits = ResourceLimits {
+            max_open_files: 256,
+            nice: 3,
+            ..Default::default()
+        };
+        let mut command = Command::new("sh");
+        command.args(["-c", "ulimit -n; ulimit -c; cut -d' ' -f19 /proc/self/stat"]);
+        apply_limits(&mut command, &limits, None).unwrap();
+        let output = command.output().await.unwrap();
+        assert!(output.status.success());
+
+        // SAFETY: getpriority has no memory-safety preconditions
+        let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS as _, 0) };
+        let expected = format!("256\n0\n{}\n", (nice + 3).min(19));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    }
+}
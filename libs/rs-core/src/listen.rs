@@ -0,0 +1,192 @@
+//! Choice of the address walletshield listens on.
+//!
+//! Walletshield reports a busy port only as a bind error in its output, after
+//! the network was downloaded. The listen address is therefore test-bound
+//! before walletshield is started, falling back to a free port from a
+//! configured range if enabled.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::ListenConfig;
+
+/// A listen address in Go's notation, where `:7070` means all interfaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenAddr {
+    /// The address to bind
+    pub bind: SocketAddr,
+    /// The local address at which the listener is reached, e.g. to probe it
+    pub probe: SocketAddr,
+}
+
+impl ListenAddr {
+    pub fn parse(listen_address: &str) -> Result<Self> {
+        let (host, port) = split_port(listen_address)?;
+        let ip = match host {
+            "" => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            "localhost" => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            host => host
+                .trim_matches(|c| c == '[' || c == ']')
+                .parse()
+                .with_context(|| format!("invalid listen address {listen_address:?}"))?,
+        };
+        let bind = SocketAddr::new(ip, port);
+        let probe = if ip.is_unspecified() {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+        } else {
+            bind
+        };
+        Ok(Self { bind, probe })
+    }
+}
+
+/// Whether `addr` can be bound.
+fn is_free(addr: SocketAddr) -> io::Result<bool> {
+    match TcpListener::bind(addr) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Claim the listen address to use in place of `listen_address`: itself if
+/// its port is free, otherwise the same address with a free port from the
+/// fallback range, if enabled. Returns the address along with its claim.
+///
+/// A port found free may still be claimed by a concurrent session before this
+/// one, so the choice is only final once `claim` succeeds, e.g. by locking the
+/// address. `claim` returns `None` for an address claimed by another session,
+/// which moves on to the next port.
+pub fn claim_listen_address<T>(
+    listen_address: &str,
+    cfg: &ListenConfig,
+    mut claim: impl FnMut(&str) -> Result<Option<T>>,
+) -> Result<(String, T)> {
+    let addr = ListenAddr::parse(listen_address)?.bind;
+    let cannot_bind = || format!("cannot listen on {listen_address}");
+    if is_free(addr).with_context(cannot_bind)? {
+        if let Some(claimed) = claim(listen_address)? {
+            return Ok((listen_address.to_owned(), claimed));
+        }
+    }
+    if !cfg.fallback {
+        bail!(
+            "listen address {listen_address} is already in use; stop the program \
+             using it, choose another address or enable listen.fallback"
+        );
+    }
+
+    let (host, _) = split_port(listen_address)?;
+    for port in cfg.fallback_port_min..=cfg.fallback_port_max {
+        if !is_free(SocketAddr::new(addr.ip(), port)).with_context(cannot_bind)? {
+            continue;
+        }
+        let effective = format!("{host}:{port}");
+        if let Some(claimed) = claim(&effective)? {
+            println!("Listen address {listen_address} is in use; using {effective}");
+            return Ok((effective, claimed));
+        }
+    }
+    bail!(
+        "listen address {listen_address} is already in use, and so are ports {}-{}",
+        cfg.fallback_port_min,
+        cfg.fallback_port_max
+    )
+}
+
+fn split_port(listen_address: &str) -> Result<(&str, u16)> {
+    listen_address
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+        .with_context(|| format!("invalid listen address {listen_address:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_addr() {
+        let addrs = |a: &str| {
+            let addr = ListenAddr::parse(a).unwrap();
+            (addr.bind.to_string(), addr.probe.to_string())
+        };
+        assert_eq!(
+            addrs(":7070"),
+            ("0.0.0.0:7070".into(), "127.0.0.1:7070".into())
+        );
+        assert_eq!(
+            addrs("0.0.0.0:7070"),
+            ("0.0.0.0:7070".into(), "127.0.0.1:7070".into())
+        );
+        assert_eq!(
+            addrs("localhost:7070"),
+            ("127.0.0.1:7070".into(), "127.0.0.1:7070".into())
+        );
+        assert_eq!(
+            addrs("[::1]:7070"),
+            ("[::1]:7070".into(), "[::1]:7070".into())
+        );
+        assert!(ListenAddr::parse("7070").is_err());
+        assert!(ListenAddr::parse("localhost").is_err());
+        assert!(ListenAddr::parse("host:7070").is_err());
+    }
+
+    #[test]
+    fn test_falls_back_to_a_free_port() {
+        let busy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = busy.local_addr().unwrap().port();
+        let listen_address = format!("127.0.0.1:{port}");
+        let resolve = |cfg: &ListenConfig| {
+            claim_listen_address(&listen_address, cfg, |_| Ok(Some(()))).map(|(a, _)| a)
+        };
+
+        let mut cfg = ListenConfig::default();
+        let err = resolve(&cfg).unwrap_err();
+        assert!(err.to_string().contains("already in use"), "{err}");
+
+        // the busy port itself is skipped
+        cfg.fallback = true;
+        cfg.fallback_port_min = port;
+        cfg.fallback_port_max = port.saturating_add(50);
+        let effective = resolve(&cfg).unwrap();
+        let (host, fallback) = split_port(&effective).unwrap();
+        assert_eq!(host, "127.0.0.1");
+        assert!(fallback > port && fallback <= cfg.fallback_port_max);
+
+        drop(busy);
+        assert_eq!(resolve(&cfg).unwrap(), listen_address);
+    }
+
+    #[test]
+    fn test_skips_ports_claimed_meanwhile() {
+        let busy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = busy.local_addr().unwrap().port();
+        let cfg = ListenConfig {
+            fallback: true,
+            fallback_port_min: port.saturating_add(1),
+            fallback_port_max: port.saturating_add(50),
+        };
+
+        // another session claims the first free port, which is not bound yet
+        let mut claimed = Vec::new();
+        let (effective, n) = claim_listen_address(&format!("127.0.0.1:{port}"), &cfg, |addr| {
+            claimed.push(addr.to_owned());
+            Ok((claimed.len() > 1).then_some(claimed.len()))
+        })
+        .unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(claimed, [claimed[0].clone(), effective.clone()]);
+        assert_ne!(claimed[0], effective);
+
+        // any other failure to claim is final
+        let err =
+            claim_listen_address::<()>(&format!("127.0.0.1:{port}"), &cfg, |_| bail!("locked"))
+                .unwrap_err();
+        assert_eq!(err.to_string(), "locked");
+    }
+}
@@ -0,0 +1,306 @@
+//! Exclusive locks on running network sessions.
+//!
+//! Only one walletshield may run per network and per listen address, even
+//! across processes: the CLI and the GUI, or two invocations of the CLI, must
+//! not start it twice. Each lock is an OS file lock on `<name>.lock`, which
+//! the OS releases when its owner dies, so that a crashed client never leaves
+//! a stale lock behind. The owner describes its session in `<name>.json`,
+//! where others can read it while the lock is held.
+//!
+//! Changing an installed network, e.g. removing it, takes a shared lock on
+//! the network's lock file instead, so that no session can start meanwhile.
+//!
+//! Lock files are never removed, as a process could otherwise lock a file
+//! that was just unlinked while another creates and locks a new one.
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions, TryLockError},
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::ClientStatus, listen::ListenAddr, paths::AppPaths, registry::validate_network_id,
+    utils::unix_now,
+};
+
+/// Start of `AlreadyRunning::what` for a listen address.
+const WHAT_LISTEN: &str = "listen address";
+
+/// Describes the session holding a lock.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockInfo {
+    /// PID of the process running the session
+    pub pid: u32,
+    /// Name of the program running the session, e.g. `zkn-client`
+    pub owner: String,
+    pub network_id: String,
+    pub listen_address: String,
+    /// Unix timestamp in seconds
+    pub started_at: u64,
+    /// Last status of the session, as published by its owner
+    pub status: Option<ClientStatus>,
+}
+
+/// A network or listen address is locked by another session.
+#[derive(Debug)]
+pub struct AlreadyRunning {
+    /// What is locked, e.g. `network net` or `listen address 127.0.0.1:7070`
+    pub what: String,
+    /// The session holding the lock, if it could be read
+    pub info: Option<LockInfo>,
+}
+
+impl fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is already running", self.what)?;
+        match &self.info {
+            Some(info) => write!(f, " (pid {}, started by {})", info.pid, info.owner),
+            None => Ok(()),
+        }
+    }
+}
+
+impl AlreadyRunning {
+    /// Whether a listen address, rather than the network, is locked.
+    pub fn is_listen_address(&self) -> bool {
+        self.what.starts_with(WHAT_LISTEN)
+    }
+}
+
+impl std::error::Error for AlreadyRunning {}
+
+/// The locks held by a session on its network and listen address, released
+/// when dropped.
+#[derive(Debug)]
+pub struct SessionLock {
+    locks: Vec<Lock>,
+    info: LockInfo,
+}
+
+impl SessionLock {
+    /// Lock `network_id` and `listen_address` for a session of this process.
+    ///
+    /// Fails with `AlreadyRunning` if either is locked by another session.
+    pub fn acquire(paths: &AppPaths, network_id: &str, listen_address: &str) -> Result<Self> {
+        validate_network_id(network_id)?;
+        let addr = ListenAddr::parse(listen_address)?.probe;
+        let dir = paths.dir_locks();
+        fs::create_dir_all(&dir)?;
+
+        let info = LockInfo {
+            pid: std::process::id(),
+            owner: owner_name(),
+            network_id: network_id.to_owned(),
+            listen_address: listen_address.to_owned(),
+            started_at: unix_now(),
+            status: None,
+        };
+        let mut locks = Vec::new();
+        for (name, what) in [
+            (name_network(network_id), format!("network {network_id}")),
+            (
+                name_listen(&addr.to_string()),
+                format!("{WHAT_LISTEN} {addr}"),
+            ),
+        ] {
+            locks.push(Lock::acquire(&dir, &name, what)?);
+        }
+
+        let lock = Self { locks, info };
+        lock.publish()?;
+        Ok(lock)
+    }
+
+    pub fn info(&self) -> &LockInfo {
+        &self.info
+    }
+
+    /// Publish the status of the session to other processes.
+    pub fn set_status(&mut self, status: ClientStatus) -> Result<()> {
+        self.info.status = Some(status);
+        self.publish()
+    }
+
+    fn publish(&self) -> Result<()> {
+        let json = serde_json::to_vec_pretty(&self.info)?;
+        for lock in &self.locks {
+            // written aside and renamed, so that readers never see a partial
+            // file
+            let path_part = lock.path_info.with_extension("json.part");
+            fs::write(&path_part, &json)?;
+            fs::rename(&path_part, &lock.path_info)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Lock {
+    // held for the lifetime of the lock; closing it releases the lock
+    _file: File,
+    path_info: PathBuf,
+}
+
+impl Lock {
+    fn acquire(dir: &Path, name: &str, what: String) -> Result<Self> {
+        let path = dir.join(format!("{name}.lock"));
+        let path_info = dir.join(format!("{name}.json"));
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self {
+                _file: file,
+                path_info,
+            }),
+            Err(TryLockError::WouldBlock) => Err(AlreadyRunning {
+                what,
+                info: read_info(&path_info),
+            }
+            .into()),
+            Err(TryLockError::Error(e)) => {
+                Err(e).with_context(|| format!("failed to lock {}", path.display()))
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // the lock itself is released when the file is closed
+        match fs::remove_file(&self.path_info) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                eprintln!("Failed to remove {}: {e}", self.path_info.display());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A network kept from running while it is changed, unlocked when dropped.
+#[derive(Debug)]
+pub struct IdleLock {
+    // held for the lifetime of the lock; closing it releases the lock
+    _file: File,
+}
+
+/// Keep `network_id` from running for as long as the returned lock is held.
+///
+/// Fails with `AlreadyRunning` if a session runs the network.
+pub fn lock_idle(paths: &AppPaths, network_id: &str) -> Result<IdleLock> {
+    validate_network_id(network_id)?;
+    let dir = paths.dir_locks();
+    fs::create_dir_all(&dir)?;
+    let name = name_network(network_id);
+    let path = dir.join(format!("{name}.lock"));
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    match file.try_lock_shared() {
+        Ok(()) => Ok(IdleLock { _file: file }),
+        Err(TryLockError::WouldBlock) => Err(AlreadyRunning {
+            what: format!("network {network_id}"),
+            info: read_info(&dir.join(format!("{name}.json"))),
+        }
+        .into()),
+        Err(TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("failed to lock {}", path.display()))
+        }
+    }
+}
+
+/// The session running a network, if any.
+pub fn running_session(paths: &AppPaths, network_id: &str) -> Result<Option<LockInfo>> {
+    validate_network_id(network_id)?;
+    let dir = paths.dir_locks();
+    let name = name_network(network_id);
+    let path = dir.join(format!("{name}.lock"));
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    match file.try_lock_shared() {
+        // nobody holds the lock; it is released again when the file closes
+        Ok(()) => Ok(None),
+        Err(TryLockError::WouldBlock) => Ok(read_info(&dir.join(format!("{name}.json")))),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+fn read_info(path: &Path) -> Option<LockInfo> {
+    let json = fs::read(path).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+fn name_network(network_id: &str) -> String {
+    format!("network-{network_id}")
+}
+
+fn name_listen(addr: &str) -> String {
+    let addr: String = addr
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("listen-{addr}")
+}
+
+/// Name of the running program, e.g. `zkn-client`.
+fn owner_name() -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.file_stem()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "unknown".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locks_network_and_listen_address() {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths = AppPaths::from_dir(tmp.path().to_path_buf());
+        assert!(running_session(&paths, "net").unwrap().is_none());
+
+        let lock = SessionLock::acquire(&paths, "net", ":7070").unwrap();
+        let info = running_session(&paths, "net").unwrap().unwrap();
+        assert_eq!(info.pid, std::process::id());
+        assert_eq!(info.listen_address, ":7070");
+
+        // the same network on another address
+        let err = SessionLock::acquire(&paths, "net", ":7071").unwrap_err();
+        let err = err.downcast::<AlreadyRunning>().unwrap();
+        assert_eq!(err.what, "network net");
+        let expected = format!(
+            "network net is already running (pid {}, started by {})",
+            info.pid, info.owner
+        );
+        assert_eq!(err.to_string(), expected);
+
+        // another network on the same address
+        let err = SessionLock::acquire(&paths, "other", "0.0.0.0:7070").unwrap_err();
+        let err = err.downcast::<AlreadyRunning>().unwrap();
+        assert_eq!(err.what, "listen address 127.0.0.1:7070");
+        assert!(err.is_listen_address());
+
+        // the network lock taken before the failure was released
+        drop(SessionLock::acquire(&paths, "other", ":7071").unwrap());
+
+        drop(lock);
+        assert!(running_session(&paths, "net").unwrap().is_none());
+        drop(SessionLock::acquire(&paths, "net", ":7070").unwrap());
+    }
+}
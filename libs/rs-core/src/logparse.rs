@@ -0,0 +1,312 @@
+//! Parsing of walletshield output into leveled records and typed events.
+//!
+//! Walletshield logs through katzenpost's logger, whose lines look like
+//! `15:04:05.000 INFO client2/conn: Connected to gateway gw1`, optionally with
+//! a date in front. Output from Go's standard logger (`2006/01/02 15:04:05
+//! message`) and panics are recognized as well; anything else is kept as a
+//! plain message.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Level {
+    Debug,
+    #[default]
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl Level {
+    fn parse(token: &str) -> Option<Self> {
+        let token = token.trim_matches(|c| c == '[' || c == ']');
+        Some(match token.to_ascii_uppercase().as_str() {
+            "DEBU" | "DEBUG" => Level::Debug,
+            "INFO" => Level::Info,
+            "NOTI" | "NOTICE" => Level::Notice,
+            "WARN" | "WARNING" => Level::Warning,
+            "ERRO" | "ERROR" => Level::Error,
+            "CRIT" | "CRITICAL" | "FATA" | "FATAL" => Level::Critical,
+            _ => return None,
+        })
+    }
+}
+
+/// Something meaningful reported by walletshield.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ClientEvent {
+    GatewayConnecting,
+    GatewayConnected,
+    GatewayDisconnected {
+        reason: String,
+    },
+    /// A PKI document (the mixnet topology) was fetched
+    PkiDocumentFetched {
+        epoch: Option<u64>,
+    },
+    /// A proxied request failed
+    RequestError {
+        message: String,
+    },
+    /// The process panicked or hit a fatal error
+    Crashed {
+        message: String,
+    },
+}
+
+/// A parsed line of walletshield output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogRecord {
+    /// Time of day (and date, if logged) as written by walletshield
+    pub time: Option<String>,
+    pub level: Level,
+    /// Logger name, e.g. `client2/conn`
+    pub module: Option<String>,
+    pub message: String,
+}
+
+impl LogRecord {
+    /// The event this record reports, if any.
+    pub fn event(&self) -> Option<ClientEvent> {
+        let msg = self.message.to_ascii_lowercase();
+        let any = |words: &[&str]| words.iter().any(|w| msg.contains(w));
+
+        if self.level == Level::Critical || msg.starts_with("panic:") {
+            return Some(ClientEvent::Crashed {
+                message: self.message.clone(),
+            });
+        }
+        if any(&["gateway", "provider"]) {
+            if any(&["disconnect", "connection lost", "failed", "closed"])
+                || self.level >= Level::Error
+            {
+                return Some(ClientEvent::GatewayDisconnected {
+                    reason: self.message.clone(),
+                });
+            }
+            // completed states first, as e.g. "reconnected" contains
+            // "reconnect"; words are matched whole
+            let words: Vec<&str> = msg
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|w| !w.is_empty())
+                .collect();
+            let connected = words.iter().enumerate().any(|(i, w)| {
+                matches!(*w, "connected" | "reconnected")
+                    && !words[..i].ends_with(&["not"])
+                    && !words[..i].ends_with(&["not", "yet"])
+            });
+            if connected || msg.contains("handshake complete") {
+                return Some(ClientEvent::GatewayConnected);
+            }
+            // including "not (yet) connected"
+            let connecting = [
+                "connecting",
+                "dialing",
+                "reconnect",
+                "reconnecting",
+                "connected",
+            ];
+            if words.iter().any(|w| connecting.contains(w)) {
+                return Some(ClientEvent::GatewayConnecting);
+            }
+        }
+        if msg.contains("pki") && any(&["document", "doc "]) && !any(&["fail", "error"]) {
+            return Some(ClientEvent::PkiDocumentFetched {
+                epoch: number_after(&msg, "epoch"),
+            });
+        }
+        if self.level >= Level::Error && any(&["request", "rpc", "http"]) {
+            return Some(ClientEvent::RequestError {
+                message: self.message.clone(),
+            });
+        }
+        None
+    }
+}
+
+/// Parse a line of walletshield output.
+pub fn parse_line(line: &str) -> LogRecord {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let mut rest = line.trim_start();
+    let mut time = Vec::new();
+
+    // leading date and time of day
+    while let Some((token, tail)) = split_token(rest) {
+        if is_date(token) || is_time(token) {
+            time.push(token);
+            rest = tail;
+        } else {
+            break;
+        }
+    }
+
+    let mut level = None;
+    if let Some((token, tail)) = split_token(rest) {
+        if let Some(l) = Level::parse(token) {
+            level = Some(l);
+            rest = tail;
+        }
+    }
+
+    // a module is only recognized after a level, as messages may contain
+    // colons too
+    let mut module = None;
+    if level.is_some() {
+        if let Some((token, tail)) = split_token(rest) {
+            if let Some(name) = token.strip_suffix(':') {
+                if !name.is_empty() {
+                    module = Some(name.to_owned());
+                    rest = tail;
+                }
+            }
+        }
+    }
+
+    let message = rest.trim().to_owned();
+    let level = level.unwrap_or_else(|| {
+        if message.starts_with("panic:") || message.starts_with("fatal error:") {
+            Level::Critical
+        } else {
+            Level::Info
+        }
+    });
+
+    LogRecord {
+        time: (!time.is_empty()).then(|| time.join(" ")),
+        level,
+        module,
+        message,
+    }
+}
+
+fn split_token(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    if s.is_empty() {
+        return None;
+    }
+    Some(match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], &s[i..]),
+        None => (s, ""),
+    })
+}
+
+/// `2006-01-02` or `2006/01/02`
+fn is_date(token: &str) -> bool {
+    let b = token.as_bytes();
+    b.len() == 10
+        && (b[4] == b'-' || b[4] == b'/')
+        && b[7] == b[4]
+        && b.iter()
+            .enumerate()
+            .all(|(i, c)| i == 4 || i == 7 || c.is_ascii_digit())
+}
+
+/// `15:04:05`, optionally with fractional seconds
+fn is_time(token: &str) -> bool {
+    let b = token.as_bytes();
+    b.len() >= 8
+        && b[2] == b':'
+        && b[5] == b':'
+        && b[..8]
+            .iter()
+            .enumerate()
+            .all(|(i, c)| i == 2 || i == 5 || c.is_ascii_digit())
+        && (b.len() == 8 || (b[8] == b'.' && b[9..].iter().all(u8::is_ascii_digit)))
+}
+
+fn number_after(msg: &str, word: &str) -> Option<u64> {
+    let tail = &msg[msg.find(word)? + word.len()..];
+    let digits: String = tail
+        .trim_start_matches(|c: char| !c.is_ascii_digit())
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_katzenpost_lines() {
+        let r = parse_line("15:04:05.000 INFO client2/conn: Connected to gateway gw1\n");
+        assert_eq!(r.time.as_deref(), Some("15:04:05.000"));
+        assert_eq!(r.level, Level::Info);
+        assert_eq!(r.module.as_deref(), Some("client2/conn"));
+        assert_eq!(r.message, "Connected to gateway gw1");
+        assert_eq!(r.event(), Some(ClientEvent::GatewayConnected));
+
+        let r =
+            parse_line("2025-01-02 15:04:05.000 WARN client2/pki: Got PKI document for epoch 1234");
+        assert_eq!(r.time.as_deref(), Some("2025-01-02 15:04:05.000"));
+        assert_eq!(r.level, Level::Warning);
+        assert_eq!(
+            r.event(),
+            Some(ClientEvent::PkiDocumentFetched { epoch: Some(1234) })
+        );
+
+        let r = parse_line("15:04:05.000 ERRO walletshield: HTTP request failed: timeout");
+        assert!(matches!(r.event(), Some(ClientEvent::RequestError { .. })));
+
+        let r = parse_line("15:04:05.000 NOTI client2/conn: Gateway connection lost: EOF");
+        assert!(matches!(
+            r.event(),
+            Some(ClientEvent::GatewayDisconnected { .. })
+        ));
+    }
+
+    #[test]
+    fn test_gateway_states() {
+        let event = |message: &str| parse_line(message).event();
+        for message in [
+            "Connected to gateway gw1",
+            "Reconnected to gateway gw1",
+            "Gateway connected",
+            "Handshake complete with provider gw1",
+        ] {
+            assert_eq!(
+                event(message),
+                Some(ClientEvent::GatewayConnected),
+                "{message}"
+            );
+        }
+        for message in [
+            "Connecting to gateway gw1",
+            "Reconnecting to gateway gw1",
+            "Dialing gateway gw1",
+            "Not connected to gateway, retrying",
+            "Gateway not yet connected",
+        ] {
+            assert_eq!(
+                event(message),
+                Some(ClientEvent::GatewayConnecting),
+                "{message}"
+            );
+        }
+        assert_eq!(event("Skipping unconnected gateway gw2"), None);
+    }
+
+    #[test]
+    fn test_parse_other_lines() {
+        let r = parse_line("2025/01/02 15:04:05 Starting http proxy on :7070");
+        assert_eq!(r.time.as_deref(), Some("2025/01/02 15:04:05"));
+        assert_eq!(r.level, Level::Info);
+        assert_eq!(r.module, None);
+        assert_eq!(r.message, "Starting http proxy on :7070");
+        assert_eq!(r.event(), None);
+
+        let r = parse_line("panic: runtime error: invalid memory address");
+        assert_eq!(r.level, Level::Critical);
+        assert!(matches!(r.event(), Some(ClientEvent::Crashed { .. })));
+
+        let r = parse_line("note: this is not a module");
+        assert_eq!(r.module, None);
+        assert_eq!(r.message, "note: this is not a module");
+    }
+}
@@ -0,0 +1,295 @@
+//! Capture of walletshield output in per-network log files.
+//!
+//! Every line is written with a timestamp to `<dir_logs>/<network_id>/
+//! walletshield.log`, which is rotated to `walletshield.log.1` and so on once
+//! it reaches the configured size. The most recent lines are also kept in
+//! memory for display while the client runs.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    config::LogConfig,
+    logparse::{parse_line, ClientEvent, Level},
+    paths::AppPaths,
+    registry::validate_network_id,
+};
+
+const FILE_LOG: &str = "walletshield.log";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogLine {
+    /// RFC 3339 time at which the line was captured
+    pub timestamp: String,
+    pub stream: Stream,
+    pub line: String,
+    pub level: Level,
+    pub event: Option<ClientEvent>,
+}
+
+/// Log of a network's walletshield output.
+///
+/// Lines are written by a thread of their own, so that a slow disk or a
+/// rotation never holds up the caller, which may be an async task.
+pub struct NetworkLog {
+    cfg: LogConfig,
+    path: PathBuf,
+    recent: Mutex<VecDeque<LogLine>>,
+    writer: mpsc::Sender<Message>,
+}
+
+enum Message {
+    Line(String),
+    /// Reply once the lines sent before were written
+    #[cfg(test)]
+    Flush(mpsc::Sender<()>),
+}
+
+/// Writes the lines of a log and rotates its files.
+struct Writer {
+    cfg: LogConfig,
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+}
+
+impl NetworkLog {
+    pub fn open(paths: &AppPaths, network_id: &str, cfg: LogConfig) -> Result<Self> {
+        let dir = dir_network_logs(paths, network_id)?;
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(FILE_LOG);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        let (tx, rx) = mpsc::channel();
+        let writer = Writer {
+            cfg: cfg.clone(),
+            path: path.clone(),
+            file: Some(file),
+            size,
+        };
+        // ends once the log is dropped and every line sent is written
+        std::thread::Builder::new()
+            .name(format!("log-{network_id}"))
+            .spawn(move || writer.run(rx))?;
+
+        Ok(Self {
+            cfg,
+            path,
+            recent: Mutex::new(VecDeque::new()),
+            writer: tx,
+        })
+    }
+
+    /// Path of the current log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a line of output, which may end with a newline.
+    ///
+    /// Returns the captured line, with the event it reports, if any. The line
+    /// is written to the log file in the background.
+    pub fn append(&self, stream: Stream, line: &str) -> LogLine {
+        let record = parse_line(line);
+        let entry = LogLine {
+            timestamp: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            stream,
+            line: line.trim_end_matches(['\r', '\n']).to_owned(),
+            level: record.level,
+            event: record.event(),
+        };
+        let tag = match stream {
+            Stream::Stdout => "out",
+            Stream::Stderr => "err",
+        };
+        let text = format!("{} {tag} {}\n", entry.timestamp, entry.line);
+        // the writer only stops once the log is dropped
+        let _ = self.writer.send(Message::Line(text));
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() >= self.cfg.buffer_lines {
+            recent.pop_front();
+        }
+        if self.cfg.buffer_lines > 0 {
+            recent.push_back(entry.clone());
+        }
+        entry
+    }
+
+    /// The last `n` lines kept in memory, oldest first.
+    pub fn recent(&self, n: usize) -> Vec<LogLine> {
+        let recent = self.recent.lock().unwrap();
+        let skip = recent.len().saturating_sub(n);
+        recent.iter().skip(skip).cloned().collect()
+    }
+
+    /// Wait until the lines appended so far were written.
+    #[cfg(test)]
+    fn flush(&self) {
+        let (tx, rx) = mpsc::channel();
+        self.writer.send(Message::Flush(tx)).unwrap();
+        rx.recv().unwrap();
+    }
+}
+
+impl Writer {
+    fn run(mut self, rx: mpsc::Receiver<Message>) {
+        for message in rx {
+            match message {
+                Message::Line(text) => {
+                    if let Err(e) = self.write(text.as_bytes()) {
+                        // keep the client running without its log file
+                        eprintln!("Failed to write {}: {e}", self.path.display());
+                        self.file = None;
+                    }
+                }
+                #[cfg(test)]
+                Message::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, text: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + text.len() as u64 > self.cfg.max_file_bytes {
+            self.file = None;
+            self.rotate()?;
+            self.file = Some(File::create(&self.path)?);
+            self.size = 0;
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(text)?;
+            self.size += text.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Shift `walletshield.log[.N]` to `.N+1`, dropping the oldest file.
+    fn rotate(&self) -> io::Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{n}", self.path.display()));
+        let keep = self.cfg.max_files.max(1);
+        for n in (1..keep).rev() {
+            let from = if n == 1 {
+                self.path.clone()
+            } else {
+                rotated(n - 1)
+            };
+            match fs::rename(&from, rotated(n)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        if keep == 1 {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Directory holding the log files of a network.
+pub fn dir_network_logs(paths: &AppPaths, network_id: &str) -> Result<PathBuf> {
+    validate_network_id(network_id)?;
+    Ok(paths.dir_logs().join(network_id))
+}
+
+/// The log files of a network, newest first.
+pub fn log_files(paths: &AppPaths, network_id: &str) -> Result<Vec<PathBuf>> {
+    let dir = dir_network_logs(paths, network_id)?;
+    let mut files = Vec::new();
+    let path = dir.join(FILE_LOG);
+    if path.exists() {
+        files.push(path);
+    }
+    for n in 1.. {
+        let path = dir.join(format!("{FILE_LOG}.{n}"));
+        if !path.exists() {
+            break;
+        }
+        files.push(path);
+    }
+    Ok(files)
+}
+
+/// The last `n` lines logged for a network, oldest first, read from its log
+/// files.
+pub fn tail_logs(paths: &AppPaths, network_id: &str, n: usize) -> Result<Vec<String>> {
+    let mut tail = VecDeque::new();
+    for path in log_files(paths, network_id)? {
+        let lines = BufReader::new(File::open(path)?)
+            .lines()
+            .collect::<io::Result<Vec<_>>>()?;
+        for line in lines.into_iter().rev() {
+            if tail.len() >= n {
+                return Ok(tail.into());
+            }
+            tail.push_front(line);
+        }
+    }
+    Ok(tail.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotates_and_tails_logs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths = AppPaths::from_dir(tmp.path().to_path_buf());
+        let cfg = LogConfig {
+            max_file_bytes: 200,
+            max_files: 3,
+            buffer_lines: 4,
+        };
+        let log = NetworkLog::open(&paths, "net", cfg).unwrap();
+        for i in 0..20 {
+            log.append(Stream::Stdout, &format!("line {i}\n"));
+        }
+        log.flush();
+
+        let files = log_files(&paths, "net").unwrap();
+        assert_eq!(files.len(), 3);
+        for file in &files {
+            assert!(fs::metadata(file).unwrap().len() <= 200);
+        }
+
+        let tail = tail_logs(&paths, "net", 3).unwrap();
+        assert_eq!(tail.len(), 3);
+        assert!(tail[0].ends_with(" out line 17"), "{}", tail[0]);
+        assert!(tail[2].ends_with(" out line 19"), "{}", tail[2]);
+
+        let recent = log.recent(10);
+        assert_eq!(recent.len(), 4);
+        assert_eq!(recent[0].line, "line 16");
+        assert_eq!(recent[3].line, "line 19");
+    }
+
+    #[test]
+    fn test_rejects_unsafe_network_ids() {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths = AppPaths::from_dir(tmp.path().to_path_buf());
+        assert!(tail_logs(&paths, "../etc", 10).is_err());
+        assert!(tail_logs(&paths, "net", 10).unwrap().is_empty());
+    }
+}
@@ -27,6 +27,8 @@ pub struct ProgressPayload {
 pub type ProgressCallback = Box<dyn FnMut(ProgressPayload) + Send + 'static>;
 
 /// Stream `url` into `writer` without buffering the whole body.
+///
+/// Returns the response headers, e.g. to record the `ETag` of the resource.
 pub async fn download<W>(
     client: &Client,
     url: &str,
@@ -34,7 +36,7 @@ pub async fn download<W>(
     mut progress: Option<ProgressCallback>,
     headers: Option<HeaderMap>,
     body: Option<String>,
-) -> anyhow::Result<()>
+) -> anyhow::Result<HeaderMap>
 where
     W: AsyncWrite + Unpin + Send,
 {
@@ -48,6 +50,7 @@ where
 
     let resp = req.send().await?.error_for_status()?;
     let total = resp.content_length().unwrap_or(0);
+    let resp_headers = resp.headers().clone();
 
     let start = Instant::now();
     let mut downloaded = 0u64;
@@ -70,7 +73,7 @@ where
     }
     writer.flush().await?;
     writer.shutdown().await?;
-    Ok(())
+    Ok(resp_headers)
 }
 
 /// Upload `file_path` with progress (simple HTTP `PUT`).
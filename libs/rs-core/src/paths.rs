@@ -4,31 +4,76 @@ use directories_next::ProjectDirs;
 
 const APP_ORGANIZATION: &str = "ZKNetwork";
 
+/// Name under which the directories shared by all apps are created
+const SHARED_NAME: &str = "zknet";
+
+#[derive(Clone)]
 pub struct AppPaths {
-    project_dirs: ProjectDirs,
+    dir_data: PathBuf,
+    dir_locks: PathBuf,
 }
 
 impl AppPaths {
     pub fn new(app_name: &str) -> Self {
         let project_dirs = ProjectDirs::from("com", APP_ORGANIZATION, app_name)
             .expect("Could not determine platform data dirs");
+        Self::from_dir(project_dirs.data_local_dir().to_path_buf()).with_shared_locks()
+    }
 
+    /// Use an explicit data directory, e.g. the one chosen by the Tauri app.
+    ///
+    /// Session locks are kept in the data directory as well, unless
+    /// `with_shared_locks` is used.
+    pub fn from_dir(dir_data: PathBuf) -> Self {
         // Ensure directory exists (create recursively)
-        let data_dir = project_dirs.data_local_dir();
-        fs::create_dir_all(data_dir)
-            .unwrap_or_else(|e| panic!("Failed to create local data dir {:?}: {e}", data_dir));
+        fs::create_dir_all(&dir_data)
+            .unwrap_or_else(|e| panic!("Failed to create local data dir {:?}: {e}", dir_data));
+
+        let dir_locks = dir_data.join("locks");
+        Self {
+            dir_data,
+            dir_locks,
+        }
+    }
 
-        Self { project_dirs }
+    /// Keep session locks in a directory shared by all apps of the user, so
+    /// that e.g. the CLI and the GUI never run the same network at once.
+    pub fn with_shared_locks(mut self) -> Self {
+        self.dir_locks = match ProjectDirs::from("com", APP_ORGANIZATION, SHARED_NAME) {
+            Some(dirs) => dirs.data_local_dir().join("locks"),
+            None => std::env::temp_dir().join(format!("{SHARED_NAME}-locks")),
+        };
+        self
     }
 
     pub fn dir_data(&self) -> PathBuf {
-        self.project_dirs.data_local_dir().to_path_buf()
+        self.dir_data.clone()
+    }
+
+    pub fn dir_networks(&self) -> PathBuf {
+        self.dir_data().join("networks")
+    }
+
+    pub fn dir_store(&self) -> PathBuf {
+        self.dir_data().join("store")
+    }
+
+    pub fn dir_staging(&self) -> PathBuf {
+        self.dir_data().join("staging")
     }
 
     pub fn dir_logs(&self) -> PathBuf {
         self.dir_data().join("logs")
     }
 
+    pub fn dir_locks(&self) -> PathBuf {
+        self.dir_locks.clone()
+    }
+
+    pub fn path_catalog(&self) -> PathBuf {
+        self.dir_data().join("catalog.json")
+    }
+
     pub fn path_settings(&self) -> PathBuf {
         self.dir_data().join("settings.json")
     }
@@ -0,0 +1,163 @@
+//! Tying the walletshield process to the lifetime of the client.
+//!
+//! A walletshield left behind by a crashed client keeps holding the listen
+//! port, which breaks the next connect. The child is therefore killed when
+//! its handle is dropped and, on Linux, when the client process dies. As a
+//! last resort, the PID of the running walletshield is recorded per network,
+//! so that a stale process can be reaped before the next start.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use tokio::process::Command;
+
+pub(crate) const FILE_PID: &str = "walletshield.pid";
+
+/// Configure `command` so that its process does not outlive the client.
+pub fn bind_to_parent(command: &mut Command) {
+    command.kill_on_drop(true);
+
+    // keep terminal signals (e.g. Ctrl-C) from reaching the client directly,
+    // so that it is only stopped via the supervisor
+    #[cfg(unix)]
+    command.process_group(0);
+
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: getpid is async-signal-safe
+        let parent = unsafe { libc::getpid() };
+        // SAFETY: only async-signal-safe functions are called between fork and
+        // exec. Note that the signal is sent when the spawning *thread* exits,
+        // which for tokio's worker threads is the end of the runtime.
+        unsafe {
+            command.pre_exec(move || {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // the parent may have died before the signal was armed
+                if libc::getppid() != parent {
+                    libc::_exit(1);
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+fn path_pid(dir_network: &Path) -> PathBuf {
+    dir_network.join(FILE_PID)
+}
+
+/// Record the PID of the network's running walletshield.
+pub fn write_pid(dir_network: &Path, pid: u32) -> Result<()> {
+    fs::write(path_pid(dir_network), pid.to_string())?;
+    Ok(())
+}
+
+pub fn remove_pid(dir_network: &Path) -> Result<()> {
+    match fs::remove_file(path_pid(dir_network)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Terminate a walletshield left running by a previous client, as recorded in
+/// the network's PID file.
+///
+/// The process is only signalled if it is still running `path_binary`, so
+/// that a reused PID never hits an unrelated process. Returns the PID of the
+/// reaped process.
+pub fn reap_stale(dir_network: &Path, path_binary: &Path) -> Result<Option<u32>> {
+    let pid = match fs::read_to_string(path_pid(dir_network)) {
+        Ok(s) => s.trim().parse::<u32>().ok(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let reaped = match pid {
+        Some(pid) if runs_binary(pid, path_binary) => {
+            println!("Stopping stale walletshield process {pid}");
+            terminate(pid);
+            Some(pid)
+        }
+        _ => None,
+    };
+    remove_pid(dir_network)?;
+    Ok(reaped)
+}
+
+/// Whether the process `pid` is running the executable at `path`.
+#[cfg(target_os = "linux")]
+fn runs_binary(pid: u32, path: &Path) -> bool {
+    let Ok(exe) = fs::read_link(format!("/proc/{pid}/exe")) else {
+        return false;
+    };
+    // the kernel reports the path with symlinks (e.g. into the blob store)
+    // resolved
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    // a binary replaced while running is reported as "<path> (deleted)"
+    let deleted = format!("{} (deleted)", path.display());
+    exe == path || exe.as_os_str() == deleted.as_str()
+}
+
+/// Without `/proc`, the identity of the process cannot be verified.
+#[cfg(not(target_os = "linux"))]
+fn runs_binary(_pid: u32, _path: &Path) -> bool {
+    false
+}
+
+/// Send `SIGTERM`, then `SIGKILL` if the process is still alive after a while.
+#[cfg(unix)]
+fn terminate(pid: u32) {
+    use std::{thread, time::Duration};
+
+    let pid = pid as libc::pid_t;
+    // SAFETY: kill has no memory-safety preconditions
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    for _ in 0..20 {
+        thread::sleep(Duration::from_millis(100));
+        // SAFETY: as above; signal 0 only checks for existence
+        if unsafe { libc::kill(pid, 0) } != 0 {
+            return;
+        }
+    }
+    // SAFETY: as above
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+}
+
+#[cfg(not(unix))]
+fn terminate(_pid: u32) {}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reaps_only_matching_processes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let sleep = Path::new("/bin/sleep").canonicalize().unwrap();
+        let mut child = std::process::Command::new(&sleep)
+            .arg("60")
+            .spawn()
+            .unwrap();
+
+        // a PID running another binary is left alone
+        write_pid(tmp.path(), child.id()).unwrap();
+        assert_eq!(
+            reap_stale(tmp.path(), Path::new("/bin/false")).unwrap(),
+            None
+        );
+        assert!(child.try_wait().unwrap().is_none());
+        assert!(!path_pid(tmp.path()).exists());
+
+        write_pid(tmp.path(), child.id()).unwrap();
+        assert_eq!(reap_stale(tmp.path(), &sleep).unwrap(), Some(child.id()));
+        assert!(child.wait().unwrap().code().is_none());
+
+        // no PID file, nothing to do
+        assert_eq!(reap_stale(tmp.path(), &sleep).unwrap(), None);
+    }
+}
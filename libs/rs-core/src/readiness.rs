@@ -0,0 +1,272 @@
+//! Detection of when a walletshield is actually ready to serve requests.
+//!
+//! Walletshield is considered ready once its log reports a connection to the
+//! mixnet gateway and its listen address accepts connections. Losing either
+//! afterwards degrades it until both are back.
+//!
+//! The gateway state is guessed from the wording of log lines, which a new
+//! walletshield version may change. So a walletshield that has been listening
+//! for a while without any news of its gateway is taken as ready as well. Once
+//! the log reported on the gateway, only a reported connection makes it ready.
+
+use std::{
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpStream, sync::watch};
+
+use crate::logparse::{parse_line, ClientEvent};
+
+/// Interval between probes of the listen address while not ready.
+const PROBE_INTERVAL_STARTING: Duration = Duration::from_millis(500);
+/// Interval between probes of the listen address once ready.
+const PROBE_INTERVAL_READY: Duration = Duration::from_secs(5);
+/// How long walletshield must have been listening, without any news of its
+/// gateway, to be ready without its log reporting a connection.
+const READY_FALLBACK_AFTER: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReadyState {
+    #[default]
+    Starting,
+    ConnectingToGateway,
+    Ready,
+    /// Was ready, but lost the gateway connection or stopped listening
+    Degraded,
+}
+
+/// Connection to the gateway, as reported by the log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GatewayState {
+    #[default]
+    Unknown,
+    Connecting,
+    Connected,
+    Lost,
+}
+
+#[derive(Debug, Default)]
+struct Observed {
+    gateway: GatewayState,
+    /// Since when the listen address accepts connections
+    listening_since: Option<Instant>,
+    was_ready: bool,
+}
+
+/// Tracks the readiness of a walletshield process.
+pub struct Readiness {
+    observed: Mutex<Observed>,
+    state: watch::Sender<ReadyState>,
+    fallback_after: Duration,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self {
+            observed: Mutex::default(),
+            state: watch::Sender::new(ReadyState::Starting),
+            fallback_after: READY_FALLBACK_AFTER,
+        }
+    }
+
+    /// Take walletshield as ready once it has been listening for
+    /// `fallback_after` without its log reporting on the gateway.
+    pub fn with_fallback_after(mut self, fallback_after: Duration) -> Self {
+        self.fallback_after = fallback_after;
+        self
+    }
+
+    pub fn state(&self) -> ReadyState {
+        *self.state.borrow()
+    }
+
+    /// Receive every change of the state.
+    pub fn subscribe(&self) -> watch::Receiver<ReadyState> {
+        self.state.subscribe()
+    }
+
+    /// Start over for a new process.
+    pub fn reset(&self) {
+        self.update(|o| *o = Observed::default());
+    }
+
+    /// Feed a line of walletshield's log output.
+    pub fn observe_line(&self, line: &str) {
+        if let Some(event) = parse_line(line).event() {
+            self.observe_event(&event);
+        }
+    }
+
+    /// Feed an event parsed from walletshield's log output.
+    pub fn observe_event(&self, event: &ClientEvent) {
+        let gateway = match event {
+            ClientEvent::GatewayConnecting => GatewayState::Connecting,
+            ClientEvent::GatewayConnected => GatewayState::Connected,
+            ClientEvent::GatewayDisconnected { .. } => GatewayState::Lost,
+            _ => return,
+        };
+        self.update(|o| o.gateway = gateway);
+    }
+
+    /// Record whether the listen address accepts connections.
+    pub fn set_listening(&self, listening: bool) {
+        self.update(|o| {
+            if !listening {
+                o.listening_since = None;
+            } else if o.listening_since.is_none() {
+                o.listening_since = Some(Instant::now());
+            }
+        });
+    }
+
+    /// Wait until ready, failing with the last state after `timeout`.
+    pub async fn wait_ready(&self, timeout: Duration) -> Result<()> {
+        let mut rx = self.subscribe();
+        let ready = async {
+            rx.wait_for(|s| *s == ReadyState::Ready)
+                .await
+                .map(|_| ())
+                .context("readiness tracker dropped")
+        };
+        match tokio::time::timeout(timeout, ready).await {
+            Ok(result) => result,
+            Err(_) => bail!(
+                "walletshield not ready after {timeout:?} (state: {:?})",
+                self.state()
+            ),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Observed)) {
+        let mut observed = self.observed.lock().unwrap();
+        f(&mut observed);
+
+        let ready =
+            observed
+                .listening_since
+                .is_some_and(|listening_since| match observed.gateway {
+                    GatewayState::Connected => true,
+                    GatewayState::Unknown => listening_since.elapsed() >= self.fallback_after,
+                    GatewayState::Connecting | GatewayState::Lost => false,
+                });
+        observed.was_ready |= ready;
+        let state = if ready {
+            ReadyState::Ready
+        } else if observed.was_ready {
+            ReadyState::Degraded
+        } else if matches!(
+            observed.gateway,
+            GatewayState::Connecting | GatewayState::Connected
+        ) {
+            ReadyState::ConnectingToGateway
+        } else {
+            ReadyState::Starting
+        };
+        self.state
+            .send_if_modified(|s| std::mem::replace(s, state) != state);
+    }
+}
+
+/// Probe `addr` for as long as the returned future is polled.
+pub async fn probe_listening(readiness: &Readiness, addr: SocketAddr) {
+    loop {
+        let connect = TcpStream::connect(addr);
+        let listening = matches!(
+            tokio::time::timeout(Duration::from_secs(1), connect).await,
+            Ok(Ok(_))
+        );
+        readiness.set_listening(listening);
+
+        let interval = if readiness.state() == ReadyState::Ready {
+            PROBE_INTERVAL_READY
+        } else {
+            PROBE_INTERVAL_STARTING
+        };
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_machine() {
+        let r = Readiness::new();
+        r.observe_line("12:00:00.000 INFO client: Started HTTP proxy");
+        assert_eq!(r.state(), ReadyState::Starting);
+        r.observe_line("12:00:00.100 INFO client: Connecting to gateway gw1");
+        assert_eq!(r.state(), ReadyState::ConnectingToGateway);
+        r.observe_line("12:00:01.000 INFO client: Connected to gateway gw1");
+        assert_eq!(r.state(), ReadyState::ConnectingToGateway);
+        r.set_listening(true);
+        assert_eq!(r.state(), ReadyState::Ready);
+
+        r.observe_line("12:05:00.000 WARNING client: Gateway connection lost");
+        assert_eq!(r.state(), ReadyState::Degraded);
+        r.observe_line("12:05:01.000 INFO client: Connecting to gateway gw1");
+        assert_eq!(r.state(), ReadyState::Degraded);
+        r.observe_line("12:05:02.000 INFO client: Connected to gateway gw1");
+        assert_eq!(r.state(), ReadyState::Ready);
+
+        r.reset();
+        assert_eq!(r.state(), ReadyState::Starting);
+    }
+
+    #[test]
+    fn test_ready_without_gateway_events() {
+        let r = Readiness::new().with_fallback_after(Duration::from_millis(50));
+        r.set_listening(true);
+        assert_eq!(r.state(), ReadyState::Starting);
+        std::thread::sleep(Duration::from_millis(60));
+        r.set_listening(true);
+        assert_eq!(r.state(), ReadyState::Ready);
+
+        // once the log reports on the gateway, it is believed
+        r.observe_line("12:05:00.000 WARNING client: Gateway connection lost");
+        std::thread::sleep(Duration::from_millis(60));
+        r.set_listening(true);
+        assert_eq!(r.state(), ReadyState::Degraded);
+        r.observe_line("12:05:01.000 INFO client: Connecting to gateway gw1");
+        std::thread::sleep(Duration::from_millis(60));
+        r.set_listening(true);
+        assert_eq!(r.state(), ReadyState::Degraded);
+        r.observe_line("12:05:02.000 INFO client: Connected to gateway gw1");
+        assert_eq!(r.state(), ReadyState::Ready);
+    }
+
+    #[test]
+    fn test_not_ready_while_connecting() {
+        let r = Readiness::new().with_fallback_after(Duration::from_millis(50));
+        r.observe_line("12:00:00.100 INFO client: Connecting to gateway gw1");
+        r.set_listening(true);
+        std::thread::sleep(Duration::from_millis(60));
+        r.set_listening(true);
+        assert_eq!(r.state(), ReadyState::ConnectingToGateway);
+    }
+
+    #[tokio::test]
+    async fn test_wait_ready() {
+        let r = Readiness::new();
+        assert!(r.wait_ready(Duration::from_millis(10)).await.is_err());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        r.observe_line("Connected to gateway");
+        tokio::select! {
+            _ = probe_listening(&r, addr) => unreachable!(),
+            result = r.wait_ready(Duration::from_secs(5)) => result.unwrap(),
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! Local registry of installed networks and their metadata.
+//!
+//! Each network directory under `dir_networks()` holds a `meta.json` that
+//! records where the network was installed from, its assets and when it was
+//! last used. Directories without metadata (e.g. from older clients) are still
+//! listed, with unknown fields left empty.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    lock::lock_idle, paths::AppPaths, store::BlobStore, updates::RemoteAsset, utils::unix_now,
+};
+
+pub(crate) const FILE_META: &str = "meta.json";
+
+/// Assets known to belong to a network installed without metadata.
+const DEFAULT_ASSETS: &[&str] = &[
+    "client.toml",
+    "services.json",
+    "walletshield",
+    "walletshield.exe",
+];
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetRecord {
+    /// Path within the network directory, `/`-separated
+    pub name: String,
+    /// Hex-encoded SHA-256 digest of the installed file
+    pub sha256: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkMeta {
+    pub id: String,
+    /// Base URL the assets were downloaded from
+    pub source_url: Option<String>,
+    /// Unix timestamp (seconds) of the last install
+    pub installed_at: Option<u64>,
+    /// Unix timestamp (seconds) of the last connect
+    pub last_connected: Option<u64>,
+    pub assets: Vec<AssetRecord>,
+    /// Server-side version of each downloaded asset, by asset name
+    #[serde(default)]
+    pub remote: BTreeMap<String, RemoteAsset>,
+    /// Assets found outdated by the last update check
+    #[serde(default)]
+    pub pending_updates: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkInfo {
+    #[serde(flatten)]
+    pub meta: NetworkMeta,
+    pub size_on_disk: u64,
+}
+
+pub struct NetworkRegistry {
+    paths: AppPaths,
+    dir: PathBuf,
+    store: Arc<BlobStore>,
+}
+
+impl NetworkRegistry {
+    pub fn new(paths: &AppPaths) -> Self {
+        Self {
+            paths: paths.clone(),
+            dir: paths.dir_networks(),
+            store: Arc::new(BlobStore::new(paths.dir_store())),
+        }
+    }
+
+    pub fn paths(&self) -> &AppPaths {
+        &self.paths
+    }
+
+    pub fn dir_network(&self, network_id: &str) -> PathBuf {
+        self.dir.join(network_id)
+    }
+
+    pub fn store(&self) -> Arc<BlobStore> {
+        self.store.clone()
+    }
+
+    /// List all installed networks, sorted by ID.
+    ///
+    /// A network that cannot be inspected, e.g. because its metadata is
+    /// corrupt, is left out with a warning so that it does not hide the
+    /// others.
+    pub fn list(&self) -> Result<Vec<NetworkInfo>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut networks = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let id = entry.file_name().to_string_lossy().into_owned();
+            if validate_network_id(&id).is_err() {
+                continue;
+            }
+            match self.inspect(&id) {
+                Ok(info) => networks.push(info),
+                Err(e) => eprintln!("Warning: skipping network {id}: {e:#}"),
+            }
+        }
+        networks.sort_by(|a, b| a.meta.id.cmp(&b.meta.id));
+        Ok(networks)
+    }
+
+    /// Metadata and disk usage of an installed network.
+    pub fn inspect(&self, network_id: &str) -> Result<NetworkInfo> {
+        let meta = self.load(network_id)?;
+        let size_on_disk = dir_size(&self.dir_network(network_id))?;
+        Ok(NetworkInfo { meta, size_on_disk })
+    }
+
+    /// Load the metadata of an installed network.
+    pub fn load(&self, network_id: &str) -> Result<NetworkMeta> {
+        validate_network_id(network_id)?;
+        let dir = self.dir_network(network_id);
+        ensure!(dir.is_dir(), "network {network_id} is not installed");
+
+        match fs::read_to_string(dir.join(FILE_META)) {
+            Ok(s) => serde_json::from_str(&s)
+                .with_context(|| format!("invalid metadata for network {network_id}")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(NetworkMeta {
+                id: network_id.to_owned(),
+                ..Default::default()
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, meta: &NetworkMeta) -> Result<()> {
+        validate_network_id(&meta.id)?;
+        let path = self.dir_network(&meta.id).join(FILE_META);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(meta)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Record a completed install of the network's assets.
+    pub fn record_install(
+        &self,
+        network_id: &str,
+        source_url: &str,
+        assets: Vec<AssetRecord>,
+        remote: BTreeMap<String, RemoteAsset>,
+    ) -> Result<()> {
+        let mut meta = self.load(network_id)?;
+        meta.source_url = Some(source_url.to_owned());
+        meta.installed_at = Some(unix_now());
+        meta.assets = assets;
+        meta.remote = remote;
+        meta.pending_updates.clear();
+        self.save(&meta)
+    }
+
+    /// Whether a network was installed with metadata and all its assets exist.
+    pub fn is_installed(&self, network_id: &str) -> bool {
+        let dir = self.dir_network(network_id);
+        self.load(network_id).is_ok_and(|meta| {
+            !meta.assets.is_empty() && meta.assets.iter().all(|a| dir.join(&a.name).exists())
+        })
+    }
+
+    pub fn record_connected(&self, network_id: &str) -> Result<()> {
+        let mut meta = self.load(network_id)?;
+        meta.last_connected = Some(unix_now());
+        self.save(&meta)
+    }
+
+    /// Remove a network, its metadata and its references into the blob store.
+    ///
+    /// Fails with `AlreadyRunning` if a session runs the network.
+    pub fn remove(&self, network_id: &str) -> Result<()> {
+        validate_network_id(network_id)?;
+        let dir = self.dir_network(network_id);
+        ensure!(dir.is_dir(), "network {network_id} is not installed");
+        let _idle = lock_idle(&self.paths, network_id)?;
+
+        self.store.release(&dir)?;
+        fs::remove_dir_all(&dir)?;
+        self.store.gc()?;
+        Ok(())
+    }
+
+    /// Remove the runtime state of a network, keeping its installed assets.
+    ///
+    /// Returns the paths that were removed. Fails with `AlreadyRunning` if a
+    /// session runs the network.
+    pub fn reset_state(&self, network_id: &str) -> Result<Vec<PathBuf>> {
+        let meta = self.load(network_id)?;
+        let _idle = lock_idle(&self.paths, network_id)?;
+        let dir = self.dir_network(network_id);
+
+        let keep = |name: &str| {
+            name == FILE_META
+                || if meta.assets.is_empty() {
+                    DEFAULT_ASSETS.contains(&name)
+                } else {
+                    // assets extracted from archives may live in subdirectories
+                    meta.assets
+                        .iter()
+                        .any(|a| a.name.split('/').next() == Some(name))
+                }
+        };
+
+        let mut removed = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if keep(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+            removed.push(path);
+        }
+        removed.sort();
+        Ok(removed)
+    }
+}
+
+/// Ensure a network ID is a single, plain path component.
+pub fn validate_network_id(network_id: &str) -> Result<()> {
+    let path = Path::new(network_id);
+    let mut components = path.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => bail!("invalid network id: {path:?}"),
+    }
+}
+
+/// Total size of the regular files below `dir`, not following symlinks.
+pub(crate) fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let ft = entry.file_type()?;
+        if ft.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if ft.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lock::{AlreadyRunning, SessionLock};
+
+    fn registry() -> (tempfile::TempDir, NetworkRegistry) {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths = AppPaths::from_dir(tmp.path().to_path_buf());
+        (tmp, NetworkRegistry::new(&paths))
+    }
+
+    #[test]
+    fn test_validate_network_id() {
+        assert!(validate_network_id("test-net").is_ok());
+        assert!(validate_network_id("").is_err());
+        assert!(validate_network_id("..").is_err());
+        assert!(validate_network_id("a/b").is_err());
+        assert!(validate_network_id("/abs").is_err());
+    }
+
+    #[test]
+    fn test_list_includes_networks_without_metadata() {
+        let (_tmp, reg) = registry();
+        assert!(reg.list().unwrap().is_empty());
+
+        fs::create_dir_all(reg.dir_network("legacy")).unwrap();
+        fs::write(reg.dir_network("legacy").join("client.toml"), "x").unwrap();
+        fs::create_dir_all(reg.dir_network("net")).unwrap();
+        reg.record_install("net", "https://example.org/net", vec![], BTreeMap::new())
+            .unwrap();
+
+        let list = reg.list().unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].meta.id, "legacy");
+        assert_eq!(list[0].size_on_disk, 1);
+        assert!(list[0].meta.installed_at.is_none());
+        assert_eq!(
+            list[1].meta.source_url.as_deref(),
+            Some("https://example.org/net")
+        );
+
+        // a corrupt network does not hide the others
+        fs::create_dir_all(reg.dir_network("broken")).unwrap();
+        fs::write(reg.dir_network("broken").join(FILE_META), "{").unwrap();
+        let list = reg.list().unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list.iter().all(|n| n.meta.id != "broken"));
+    }
+
+    #[test]
+    fn test_reset_state_keeps_assets() {
+        let (_tmp, reg) = registry();
+        let dir = reg.dir_network("net");
+        fs::create_dir_all(dir.join("state")).unwrap();
+        fs::write(dir.join("client.toml"), "x").unwrap();
+        fs::write(dir.join("pki.cache"), "x").unwrap();
+        reg.record_install(
+            "net",
+            "https://example.org/net",
+            vec![AssetRecord {
+                name: "client.toml".into(),
+                sha256: String::new(),
+                size: 1,
+            }],
+            BTreeMap::new(),
+        )
+        .unwrap();
+
+        let removed = reg.reset_state("net").unwrap();
+        assert_eq!(removed, vec![dir.join("pki.cache"), dir.join("state")]);
+        assert!(dir.join("client.toml").exists());
+        assert!(dir.join(FILE_META).exists());
+
+        reg.remove("net").unwrap();
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_running_network_is_not_changed() {
+        let (tmp, reg) = registry();
+        fs::create_dir_all(reg.dir_network("net")).unwrap();
+        fs::write(reg.dir_network("net").join("pki.cache"), "x").unwrap();
+
+        let paths = AppPaths::from_dir(tmp.path().to_path_buf());
+        let lock = SessionLock::acquire(&paths, "net", "127.0.0.1:0").unwrap();
+        for err in [
+            reg.remove("net").unwrap_err(),
+            reg.reset_state("net").unwrap_err(),
+        ] {
+            assert!(err.is::<AlreadyRunning>(), "{err:#}");
+        }
+        assert!(reg.dir_network("net").join("pki.cache").exists());
+
+        drop(lock);
+        reg.reset_state("net").unwrap();
+        reg.remove("net").unwrap();
+    }
+}
@@ -0,0 +1,495 @@
+//! Routing of chain requests to the network serving the chain.
+//!
+//! Each connected network runs its own walletshield, which serves the chains
+//! listed in the `RPCEndpoints` of the network's `services.json`. The router
+//! listens on a single local address and forwards every request to the
+//! walletshield of the network serving it, so that wallets need to know
+//! neither which networks are connected nor the ports they listen on:
+//!
+//! - `/chain/<chainId>` goes to the endpoint of that chain ID;
+//! - any other path goes to the endpoint whose `rpcPath` it starts with.
+//!
+//! If several connected networks serve the same chain, the first network by
+//! ID wins.
+//!
+//! Requests must address the router by IP address or as `localhost`, so that
+//! a web page cannot reach it through a DNS name of its own rebound to it.
+//! Bodies are streamed both ways; protocol upgrades, such as to WebSocket,
+//! are refused. A walletshield that stops answering is reported as a gateway
+//! timeout.
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    convert::Infallible,
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use hyper::{
+    body::Incoming,
+    header::{CONNECTION, HOST, TE, TRAILER, TRANSFER_ENCODING, UPGRADE},
+    http::uri::Authority,
+    server::conn::http1,
+    service::service_fn,
+    HeaderMap, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use reqwest::{Body, Client};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::{client::ClientStatus, listen::ListenAddr, sessions::NetworkSessions};
+
+/// Prefix of the paths addressing a chain by its ID.
+const PREFIX_CHAIN: &str = "/chain/";
+
+/// How long a walletshield may take to accept a connection, which it does
+/// locally.
+const UPSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a walletshield may go without sending anything, which includes
+/// the round trip of a request through the mixnet.
+const UPSTREAM_READ_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// An RPC endpoint listed in a network's `services.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcEndpoint {
+    pub chain: String,
+    pub network: String,
+    pub chain_id: Option<u64>,
+    pub rpc_path: String,
+    pub is_testnet: bool,
+}
+
+#[derive(Deserialize)]
+struct Services {
+    #[serde(rename = "RPCEndpoints", default)]
+    rpc_endpoints: Vec<RpcEndpoint>,
+}
+
+/// The endpoints a network serves, as listed in its `services.json`.
+pub fn read_endpoints(dir_network: &Path) -> Result<Vec<RpcEndpoint>> {
+    let path = dir_network.join("services.json");
+    let json = fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+    let services: Services = serde_json::from_slice(&json)
+        .with_context(|| format!("invalid services file {}", path.display()))?;
+    Ok(services.rpc_endpoints)
+}
+
+/// An endpoint served by a connected network's walletshield.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Route {
+    pub network_id: String,
+    /// Address of the network's walletshield
+    pub upstream: SocketAddr,
+    #[serde(flatten)]
+    pub endpoint: RpcEndpoint,
+}
+
+/// The routes of the connected networks.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RouteTable {
+    pub routes: Vec<Route>,
+}
+
+impl RouteTable {
+    /// Routes to the endpoints of every running session.
+    ///
+    /// A network whose services cannot be read is left out.
+    fn load(sessions: &NetworkSessions, cache: &EndpointCache) -> Self {
+        let dir_networks = sessions.context().paths.dir_networks();
+        let mut routes = Vec::new();
+        for status in sessions.status().into_iter().filter(|s| s.running) {
+            let Some(upstream) = status
+                .listen_address
+                .as_deref()
+                .and_then(|a| ListenAddr::parse(a).ok())
+                .map(|a| a.probe)
+            else {
+                continue;
+            };
+            let Some(endpoints) = cache.get(&dir_networks, &status) else {
+                continue;
+            };
+            routes.extend(endpoints.iter().map(|endpoint| Route {
+                network_id: status.network_id.clone(),
+                upstream,
+                endpoint: endpoint.clone(),
+            }));
+        }
+        Self { routes }
+    }
+
+    /// The route of a request path, and the path to request upstream.
+    pub fn resolve(&self, path: &str) -> Option<(&Route, String)> {
+        if let Some(rest) = path.strip_prefix(PREFIX_CHAIN) {
+            let (chain_id, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            let chain_id: u64 = chain_id.parse().ok()?;
+            let route = self
+                .routes
+                .iter()
+                .find(|r| r.endpoint.chain_id == Some(chain_id))?;
+            let rpc_path = route.endpoint.rpc_path.trim_end_matches('/');
+            return Some((route, format!("{rpc_path}{rest}")));
+        }
+
+        // the longest matching path wins, so that `/eth` does not shadow
+        // `/eth/sepolia`, and the first network among those serving it
+        self.routes
+            .iter()
+            .filter(|r| {
+                let rpc_path = r.endpoint.rpc_path.trim_end_matches('/');
+                path.strip_prefix(rpc_path)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .min_by_key(|r| Reverse(r.endpoint.rpc_path.trim_end_matches('/').len()))
+            .map(|r| (r, path.to_owned()))
+    }
+}
+
+/// The endpoints of each network, read once per walletshield process, as its
+/// `services.json` only changes when the network is installed before the
+/// process is started.
+#[derive(Default)]
+struct EndpointCache {
+    networks: Mutex<HashMap<String, CachedEndpoints>>,
+}
+
+struct CachedEndpoints {
+    /// PID of the process the endpoints were read for
+    pid: u32,
+    endpoints: Arc<[RpcEndpoint]>,
+}
+
+impl EndpointCache {
+    /// The endpoints of a running network, if its process was spawned.
+    fn get(&self, dir_networks: &Path, status: &ClientStatus) -> Option<Arc<[RpcEndpoint]>> {
+        let pid = status.process.pid?;
+        let mut networks = self.networks.lock().unwrap();
+        match networks.get(&status.network_id) {
+            Some(cached) if cached.pid == pid => Some(cached.endpoints.clone()),
+            _ => {
+                let endpoints: Arc<[RpcEndpoint]> =
+                    read_endpoints(&dir_networks.join(&status.network_id))
+                        .ok()?
+                        .into();
+                let cached = CachedEndpoints {
+                    pid,
+                    endpoints: endpoints.clone(),
+                };
+                networks.insert(status.network_id.clone(), cached);
+                Some(endpoints)
+            }
+        }
+    }
+}
+
+/// A running router, stopped when dropped.
+pub struct Router {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl Router {
+    /// Listen on `listen_address` and route requests to the networks of
+    /// `sessions`.
+    pub async fn start(sessions: Arc<NetworkSessions>, listen_address: &str) -> Result<Self> {
+        let listener = TcpListener::bind(ListenAddr::parse(listen_address)?.bind)
+            .await
+            .with_context(|| format!("cannot listen on {listen_address}"))?;
+        let local_addr = listener.local_addr()?;
+        let http = Client::builder()
+            .no_proxy()
+            .connect_timeout(UPSTREAM_CONNECT_TIMEOUT)
+            .read_timeout(UPSTREAM_READ_TIMEOUT)
+            .build()?;
+        let cache = Arc::new(EndpointCache::default());
+
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (sessions, cache, http) = (sessions.clone(), cache.clone(), http.clone());
+                tokio::spawn(async move {
+                    let svc = service_fn(move |req| {
+                        handle(req, sessions.clone(), cache.clone(), http.clone())
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), svc)
+                        .await;
+                });
+            }
+        });
+        Ok(Self { local_addr, task })
+    }
+
+    /// Address the router listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn handle(
+    req: Request<Incoming>,
+    sessions: Arc<NetworkSessions>,
+    cache: Arc<EndpointCache>,
+    http: Client,
+) -> Result<Response<Body>, Infallible> {
+    // a web page may point a name of its own at this address, so only
+    // requests meant for this host are served
+    if !is_local_host(req.headers()) {
+        let message = "requests must address the router by IP or as localhost".to_owned();
+        return Ok(respond(StatusCode::FORBIDDEN, message));
+    }
+    if req.headers().contains_key(UPGRADE) {
+        let message = "protocol upgrades are not supported".to_owned();
+        return Ok(respond(StatusCode::NOT_IMPLEMENTED, message));
+    }
+
+    let path = req.uri().path().to_owned();
+    let table = RouteTable::load(&sessions, &cache);
+    let Some((route, path_upstream)) = table.resolve(&path) else {
+        let message = format!("no connected network serves {path}");
+        return Ok(respond(StatusCode::NOT_FOUND, message));
+    };
+
+    let mut url = format!("http://{}{path_upstream}", route.upstream);
+    if let Some(query) = req.uri().query() {
+        url = format!("{url}?{query}");
+    }
+    let network_id = route.network_id.clone();
+    Ok(forward(req, &http, &url).await.unwrap_or_else(|e| {
+        let message = format!("network {network_id} did not answer: {e:#}");
+        respond(error_status(&e), message)
+    }))
+}
+
+/// The status reporting a failure to forward a request.
+fn error_status(e: &anyhow::Error) -> StatusCode {
+    match e.downcast_ref::<reqwest::Error>() {
+        Some(e) if e.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+/// Send `req` to `url` and relay the response, streaming both bodies.
+async fn forward(req: Request<Incoming>, http: &Client, url: &str) -> Result<Response<Body>> {
+    let (parts, body) = req.into_parts();
+    let res = http
+        .request(parts.method, url)
+        .headers(end_to_end(parts.headers))
+        .body(Body::wrap(body))
+        .send()
+        .await?;
+
+    let mut relayed = Response::from(res);
+    let headers = std::mem::take(relayed.headers_mut());
+    *relayed.headers_mut() = end_to_end(headers);
+    Ok(relayed)
+}
+
+/// Whether the `Host` of a request is an IP address or `localhost`, or
+/// missing. A DNS name could have been rebound to this host by a web page.
+fn is_local_host(headers: &HeaderMap) -> bool {
+    let Some(host) = headers.get(HOST) else {
+        return true;
+    };
+    let Some(authority) = host.to_str().ok().and_then(|h| h.parse::<Authority>().ok()) else {
+        return false;
+    };
+    let host = authority.host();
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok()
+}
+
+/// `headers` without those that apply to a single connection.
+fn end_to_end(mut headers: HeaderMap) -> HeaderMap {
+    for name in [CONNECTION, HOST, TE, TRAILER, TRANSFER_ENCODING, UPGRADE] {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+    headers
+}
+
+fn respond(status: StatusCode, message: String) -> Response<Body> {
+    let mut res = Response::new(Body::from(message));
+    *res.status_mut() = status;
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supervisor::SupervisorStatus;
+
+    fn route(network_id: &str, port: u16, chain_id: Option<u64>, rpc_path: &str) -> Route {
+        Route {
+            network_id: network_id.into(),
+            upstream: SocketAddr::from(([127, 0, 0, 1], port)),
+            endpoint: RpcEndpoint {
+                chain: "ethereum".into(),
+                network: "mainnet".into(),
+                chain_id,
+                rpc_path: rpc_path.into(),
+                is_testnet: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_resolves_chain_id_and_rpc_path() {
+        let table = RouteTable {
+            routes: vec![
+                route("net-a", 7070, Some(1), "/eth"),
+                route("net-b", 7071, Some(11155111), "/eth/sepolia/"),
+                route("net-b", 7071, Some(1), "/eth-b"),
+            ],
+        };
+        let resolve = |path| {
+            let (route, path) = table.resolve(path)?;
+            Some((route.network_id.as_str(), path))
+        };
+
+        assert_eq!(resolve("/chain/1"), Some(("net-a", "/eth".into())));
+        assert_eq!(
+            resolve("/chain/11155111/ws"),
+            Some(("net-b", "/eth/sepolia/ws".into()))
+        );
+        assert_eq!(resolve("/chain/5"), None);
+        assert_eq!(resolve("/chain/x"), None);
+
+        assert_eq!(resolve("/eth"), Some(("net-a", "/eth".into())));
+        assert_eq!(
+            resolve("/eth/sepolia"),
+            Some(("net-b", "/eth/sepolia".into()))
+        );
+        assert_eq!(resolve("/eth-b/x"), Some(("net-b", "/eth-b/x".into())));
+        assert_eq!(resolve("/ethx"), None);
+        assert_eq!(resolve("/"), None);
+    }
+
+    #[test]
+    fn test_first_network_wins() {
+        let table = RouteTable {
+            routes: vec![
+                route("net-a", 7070, Some(1), "/eth"),
+                route("net-b", 7071, Some(1), "/eth/"),
+                route("net-c", 7072, Some(1), "/eth"),
+            ],
+        };
+        for path in ["/eth", "/eth/x", "/chain/1"] {
+            let (route, _) = table.resolve(path).unwrap();
+            assert_eq!(route.network_id, "net-a", "{path}");
+        }
+    }
+
+    #[test]
+    fn test_only_serves_local_hosts() {
+        let headers = |host: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(HOST, host.parse().unwrap());
+            headers
+        };
+        assert!(is_local_host(&HeaderMap::new()));
+        for host in [
+            "localhost:7100",
+            "LOCALHOST",
+            "127.0.0.1:7100",
+            "[::1]:7100",
+        ] {
+            assert!(is_local_host(&headers(host)), "{host}");
+        }
+        for host in ["evil.example:7100", "localhost.evil.example", "a b"] {
+            assert!(!is_local_host(&headers(host)), "{host}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_are_reported() {
+        // accepts connections, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let http = Client::builder()
+            .read_timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let e = http.get(&url).send().await.unwrap_err();
+        assert_eq!(error_status(&e.into()), StatusCode::GATEWAY_TIMEOUT);
+
+        drop(listener);
+        let e = http.get(&url).send().await.unwrap_err();
+        assert_eq!(error_status(&e.into()), StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn test_endpoints_are_read_once_per_process() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("net");
+        fs::create_dir_all(&dir).unwrap();
+        let services = |rpc_path: &str| {
+            let json = format!(
+                r#"{{"RPCEndpoints": [{{"chain": "ethereum", "network": "mainnet",
+                    "rpcPath": "{rpc_path}", "isTestnet": false}}]}}"#
+            );
+            fs::write(dir.join("services.json"), json).unwrap();
+        };
+        let status = |pid| ClientStatus {
+            network_id: "net".into(),
+            running: true,
+            ready: Default::default(),
+            listen_address: Some("127.0.0.1:7070".into()),
+            process: SupervisorStatus {
+                pid,
+                ..Default::default()
+            },
+            error: None,
+            sandbox: Default::default(),
+        };
+        let rpc_path = |cache: &EndpointCache, pid| {
+            let endpoints = cache.get(tmp.path(), &status(pid))?;
+            Some(endpoints[0].rpc_path.clone())
+        };
+
+        let cache = EndpointCache::default();
+        services("/eth");
+        assert_eq!(rpc_path(&cache, None), None);
+        assert_eq!(rpc_path(&cache, Some(1)).as_deref(), Some("/eth"));
+        services("/eth2");
+        assert_eq!(rpc_path(&cache, Some(1)).as_deref(), Some("/eth"));
+        assert_eq!(rpc_path(&cache, Some(2)).as_deref(), Some("/eth2"));
+    }
+
+    #[test]
+    fn test_reads_endpoints() {
+        let tmp = tempfile::tempdir().unwrap();
+        let services = r#"{"RPCEndpoints": [{
+            "chain": "ethereum",
+            "network": "sepolia",
+            "chainId": 11155111,
+            "rpcPath": "/eth/sepolia",
+            "isTestnet": true
+        }]}"#;
+        fs::write(tmp.path().join("services.json"), services).unwrap();
+        let endpoints = read_endpoints(tmp.path()).unwrap();
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(endpoints[0].chain_id, Some(11155111));
+        assert!(endpoints[0].is_testnet);
+
+        fs::write(tmp.path().join("services.json"), "{}").unwrap();
+        assert!(read_endpoints(tmp.path()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,550 @@
+//! Opt-in sandbox of the walletshield process on Linux.
+//!
+//! The downloaded walletshield otherwise runs with all the privileges of the
+//! user. Between fork and exec, the child
+//!
+//! - drops its capabilities and sets `no_new_privs`, so that it cannot gain
+//!   any back by executing a setuid binary;
+//! - restricts its filesystem access with Landlock to its network directory,
+//!   its binary and the few system files a Go program reads;
+//! - denies syscalls that walletshield has no use for with seccomp, such as
+//!   `mount`, `ptrace` or `bpf`.
+//!
+//! Each protection that the running kernel does not support is skipped, and
+//! reported as such, rather than failing the start. The child reports the
+//! protections it actually applied over a pipe before executing walletshield.
+
+use std::{fmt, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::config::SandboxConfig;
+
+/// State of a protection of the sandbox.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Protection {
+    Active,
+    /// Enabled, but not supported by the running system
+    Unsupported,
+    #[default]
+    Disabled,
+}
+
+impl Protection {
+    fn from_byte(b: u8) -> Option<Self> {
+        [Self::Active, Self::Unsupported, Self::Disabled]
+            .into_iter()
+            .find(|p| *p as u8 == b)
+    }
+}
+
+impl fmt::Display for Protection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Active => "active",
+            Self::Unsupported => "unsupported",
+            Self::Disabled => "disabled",
+        })
+    }
+}
+
+/// The protections of walletshield's sandbox.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SandboxReport {
+    /// Filesystem access restricted with Landlock
+    pub filesystem: Protection,
+    /// Dangerous syscalls denied with seccomp
+    pub syscalls: Protection,
+    pub capabilities: Protection,
+}
+
+impl fmt::Display for SandboxReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "filesystem {}, syscalls {}, capabilities {}",
+            self.filesystem, self.syscalls, self.capabilities
+        )
+    }
+}
+
+impl SandboxReport {
+    fn to_bytes(&self) -> [u8; 3] {
+        [
+            self.filesystem as u8,
+            self.syscalls as u8,
+            self.capabilities as u8,
+        ]
+    }
+
+    fn from_bytes(b: [u8; 3]) -> Option<Self> {
+        Some(Self {
+            filesystem: Protection::from_byte(b[0])?,
+            syscalls: Protection::from_byte(b[1])?,
+            capabilities: Protection::from_byte(b[2])?,
+        })
+    }
+}
+
+/// The protections a child will report having applied once it was spawned.
+pub struct PendingReport {
+    /// Reported as is if there is no child to ask
+    report: SandboxReport,
+    pipe: Option<std::io::PipeReader>,
+}
+
+impl PendingReport {
+    /// The protections the child applied; to be read once it was spawned.
+    pub fn read(self) -> Result<SandboxReport> {
+        use std::io::Read;
+
+        let Some(mut pipe) = self.pipe else {
+            return Ok(self.report);
+        };
+        // written before exec, which a successful spawn waited for
+        let mut bytes = [0; 3];
+        pipe.read_exact(&mut bytes)
+            .context("failed to read the sandbox status of walletshield")?;
+        SandboxReport::from_bytes(bytes).context("invalid sandbox status of walletshield")
+    }
+}
+
+/// The protections the sandbox supports on this system.
+#[cfg(target_os = "linux")]
+fn supported(cfg: &SandboxConfig) -> SandboxReport {
+    if !cfg.enabled {
+        return SandboxReport::default();
+    }
+    let supported = |yes| {
+        if yes {
+            Protection::Active
+        } else {
+            Protection::Unsupported
+        }
+    };
+    SandboxReport {
+        filesystem: supported(linux::landlock_supported()),
+        syscalls: supported(linux::seccomp_supported()),
+        capabilities: Protection::Active,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn supported(cfg: &SandboxConfig) -> SandboxReport {
+    let protection = match cfg.enabled {
+        true => Protection::Unsupported,
+        false => Protection::Disabled,
+    };
+    SandboxReport {
+        filesystem: protection,
+        syscalls: protection,
+        capabilities: protection,
+    }
+}
+
+/// Configure `command`, running the walletshield at `path_binary` in
+/// `dir_network`, to run in the sandbox, returning the protections it will
+/// have applied.
+#[cfg(target_os = "linux")]
+pub fn apply_sandbox(
+    command: &mut Command,
+    cfg: &SandboxConfig,
+    dir_network: &Path,
+    path_binary: &Path,
+) -> Result<PendingReport> {
+    use std::os::fd::{AsRawFd, OwnedFd};
+
+    let supported = supported(cfg);
+    if !cfg.enabled {
+        return Ok(PendingReport {
+            report: supported,
+            pipe: None,
+        });
+    }
+
+    // prepared before forking, as the child may not allocate
+    let mut ruleset = match supported.filesystem {
+        Protection::Active => Some(linux::landlock_ruleset(cfg, dir_network, path_binary)?),
+        _ => None,
+    };
+    let ruleset_fd: Option<OwnedFd> = match &ruleset {
+        Some(ruleset) => ruleset.try_clone()?.into(),
+        None => None,
+    };
+    let filter = match supported.syscalls {
+        Protection::Active => Some(linux::seccomp_filter()?),
+        _ => None,
+    };
+    // both ends are closed on exec
+    let (reader, writer) = std::io::pipe()?;
+
+    // SAFETY: only async-signal-safe functions are called between fork and
+    // exec
+    unsafe {
+        command.pre_exec(move || {
+            use std::io::Error;
+
+            linux::drop_capabilities()?;
+            let mut applied = SandboxReport {
+                capabilities: Protection::Active,
+                ..supported
+            };
+            if let Some(fd) = &ruleset_fd {
+                linux::allow_read_proc_self(fd.as_raw_fd())?;
+            }
+            if let Some(ruleset) = ruleset.take() {
+                let status = ruleset
+                    .restrict_self()
+                    .map_err(|_| Error::last_os_error())?;
+                if status.ruleset == landlock::RulesetStatus::NotEnforced {
+                    applied.filesystem = Protection::Unsupported;
+                }
+            }
+            if let Some(filter) = &filter {
+                seccompiler::apply_filter(filter).map_err(|_| Error::last_os_error())?;
+            }
+
+            let bytes = applied.to_bytes();
+            let written = libc::write(writer.as_raw_fd(), bytes.as_ptr().cast(), bytes.len());
+            if written != bytes.len() as isize {
+                return Err(Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(PendingReport {
+        report: supported,
+        pipe: Some(reader),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply_sandbox(
+    _command: &mut Command,
+    cfg: &SandboxConfig,
+    _dir_network: &Path,
+    _path_binary: &Path,
+) -> Result<PendingReport> {
+    Ok(PendingReport {
+        report: supported(cfg),
+        pipe: None,
+    })
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{collections::BTreeMap, io, os::fd::RawFd, path::Path};
+
+    use anyhow::Result;
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreated,
+        RulesetCreatedAttr, ABI,
+    };
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+
+    use crate::config::SandboxConfig;
+
+    /// Newest Landlock ABI whose access rights are handled; older kernels
+    /// enforce what they support.
+    const LANDLOCK_ABI: ABI = ABI::V5;
+
+    /// Files read by walletshield as a Go program: name resolution, TLS roots,
+    /// time zones and the memory settings its runtime checks. Its own
+    /// `/proc/self` is allowed by the child itself, as only the child can
+    /// resolve it to its own PID.
+    const PATHS_READ: &[&str] = &[
+        "/etc/hosts",
+        "/etc/localtime",
+        "/etc/nsswitch.conf",
+        "/etc/resolv.conf",
+        "/etc/ssl",
+        "/etc/pki",
+        "/etc/ca-certificates",
+        "/usr/share/ca-certificates",
+        "/usr/share/zoneinfo",
+        "/dev/urandom",
+        "/sys/kernel/mm/transparent_hugepage",
+    ];
+
+    /// Libraries of a dynamically linked binary.
+    const PATHS_EXECUTE: &[&str] = &[
+        "/etc/ld.so.cache",
+        "/lib",
+        "/lib64",
+        "/usr/lib",
+        "/usr/lib64",
+    ];
+
+    /// Syscalls denied to walletshield: administration of the system, other
+    /// processes and namespaces, and kernel attack surface it never needs.
+    const SYSCALLS_DENIED: &[i64] = &[
+        libc::SYS_acct,
+        libc::SYS_add_key,
+        libc::SYS_bpf,
+        libc::SYS_chroot,
+        libc::SYS_delete_module,
+        libc::SYS_finit_module,
+        libc::SYS_fsconfig,
+        libc::SYS_fsmount,
+        libc::SYS_fsopen,
+        libc::SYS_init_module,
+        libc::SYS_kexec_file_load,
+        libc::SYS_kexec_load,
+        libc::SYS_keyctl,
+        libc::SYS_mount,
+        libc::SYS_move_mount,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_open_tree,
+        libc::SYS_perf_event_open,
+        libc::SYS_pivot_root,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_ptrace,
+        libc::SYS_quotactl,
+        libc::SYS_reboot,
+        libc::SYS_request_key,
+        libc::SYS_setns,
+        libc::SYS_swapoff,
+        libc::SYS_swapon,
+        libc::SYS_umount2,
+        libc::SYS_unshare,
+        libc::SYS_userfaultfd,
+    ];
+
+    /// `LANDLOCK_RULE_PATH_BENEATH`
+    const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+    /// `LANDLOCK_ACCESS_FS_READ_FILE | LANDLOCK_ACCESS_FS_READ_DIR`
+    const LANDLOCK_ACCESS_READ: u64 = (1 << 2) | (1 << 3);
+
+    /// `struct landlock_path_beneath_attr`
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: RawFd,
+    }
+
+    /// Version 3 of the capability sets, with two 32-bit words per set.
+    const CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: libc::c_int,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+
+    pub fn landlock_supported() -> bool {
+        // SAFETY: a null attribute with this flag only queries the ABI version
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<libc::c_void>(),
+                0usize,
+                1u32, // LANDLOCK_CREATE_RULESET_VERSION
+            )
+        };
+        abi >= 1
+    }
+
+    pub fn seccomp_supported() -> bool {
+        // fails with EINVAL on kernels built without seccomp
+        // SAFETY: PR_GET_SECCOMP has no memory-safety preconditions
+        let supported = unsafe { libc::prctl(libc::PR_GET_SECCOMP, 0, 0, 0, 0) } >= 0;
+        supported && TargetArch::try_from(std::env::consts::ARCH).is_ok()
+    }
+
+    /// Read access to the system files, execution of the binary and full
+    /// access to the network directory.
+    pub fn landlock_ruleset(
+        cfg: &SandboxConfig,
+        dir_network: &Path,
+        path_binary: &Path,
+    ) -> Result<RulesetCreated> {
+        // the binary is a link into the blob store, which must be reachable
+        // as resolved
+        let path_binary = path_binary.canonicalize()?;
+        let read = AccessFs::from_read(LANDLOCK_ABI) & !AccessFs::Execute;
+        let ruleset = Ruleset::default()
+            .handle_access(AccessFs::from_all(LANDLOCK_ABI))?
+            .create()?
+            .add_rules(path_beneath_rules(PATHS_READ, read))?
+            .add_rules(path_beneath_rules(&cfg.allow_read, read))?
+            .add_rules(path_beneath_rules(
+                PATHS_EXECUTE
+                    .iter()
+                    .map(Path::new)
+                    .chain([path_binary.as_path()]),
+                AccessFs::from_read(LANDLOCK_ABI),
+            ))?
+            .add_rules(path_beneath_rules(
+                [dir_network, Path::new("/dev/null")],
+                AccessFs::from_all(LANDLOCK_ABI),
+            ))?;
+        Ok(ruleset)
+    }
+
+    /// Allow reading the calling process's `/proc/self` in `ruleset`, which
+    /// is not restricted yet.
+    ///
+    /// Async-signal-safe.
+    pub fn allow_read_proc_self(ruleset: RawFd) -> io::Result<()> {
+        // SAFETY: the path is a C string, and the attribute matches the
+        // layout the kernel expects
+        unsafe {
+            let fd = libc::open(c"/proc/self".as_ptr(), libc::O_PATH | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let attr = PathBeneathAttr {
+                allowed_access: LANDLOCK_ACCESS_READ,
+                parent_fd: fd,
+            };
+            let added = libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset,
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr,
+                0u32,
+            );
+            let result = match added {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            };
+            libc::close(fd);
+            result
+        }
+    }
+
+    /// A filter failing the denied syscalls with `EPERM`.
+    pub fn seccomp_filter() -> Result<BpfProgram> {
+        let rules = SYSCALLS_DENIED
+            .iter()
+            .map(|&nr| (nr, vec![]))
+            .collect::<BTreeMap<_, _>>();
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::EPERM as u32),
+            TargetArch::try_from(std::env::consts::ARCH)?,
+        )?;
+        Ok(filter.try_into()?)
+    }
+
+    /// Clear every capability set and forbid gaining privileges through
+    /// execve.
+    ///
+    /// The bounding set cannot be cleared without `CAP_SETPCAP`, but with
+    /// `no_new_privs` it no longer matters. Async-signal-safe.
+    pub fn drop_capabilities() -> io::Result<()> {
+        let mut header = CapHeader {
+            version: CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let data = [CapData::default(); 2];
+        // SAFETY: the header and data match the layout of capset version 3
+        unsafe {
+            if libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // ambient capabilities were cleared along with the permitted set
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sandbox_applies_to_child() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("allowed"), "ok\n").unwrap();
+        let cfg = SandboxConfig {
+            enabled: true,
+            allow_read: Vec::new(),
+        };
+        let thp = std::fs::read("/sys/kernel/mm/transparent_hugepage/enabled").is_ok();
+
+        // shell builtins only, as other binaries are outside the sandbox
+        let script = r#"
+            read a < allowed && echo "$a"
+            if read b < /etc/passwd; then echo read; else echo denied; fi
+            if read b < /proc/1/stat; then echo read; else echo denied; fi
+            if read b < /sys/kernel/mm/transparent_hugepage/enabled; then echo thp; fi
+            read b < /proc/self/maps && echo maps
+            while read -r l; do
+                case "$l" in CapEff*|NoNewPrivs*|Seccomp:*) echo "$l";; esac
+            done < /proc/self/status
+        "#;
+        let mut command = Command::new("sh");
+        command.current_dir(tmp.path()).args(["-c", script]);
+        let pending = apply_sandbox(&mut command, &cfg, tmp.path(), Path::new("/bin/sh")).unwrap();
+        let child = command
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let report = pending.read().unwrap();
+        assert_eq!(report.capabilities, Protection::Active);
+        assert_ne!(report.syscalls, Protection::Disabled);
+
+        let output = child.wait_with_output().await.unwrap();
+        let lines: Vec<_> = String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect();
+        assert_eq!(lines[0], "ok");
+        let expected = match report.filesystem {
+            Protection::Active => "denied",
+            _ => "read",
+        };
+        assert_eq!(lines[1], expected);
+        assert_eq!(lines[2], expected);
+        let lines = match thp {
+            true => {
+                assert_eq!(lines[3], "thp");
+                &lines[4..]
+            }
+            false => &lines[3..],
+        };
+        assert_eq!(lines[0], "maps");
+        let status = lines[1..].join(" ");
+        assert!(status.contains("CapEff: 0000000000000000"), "{status}");
+        assert!(status.contains("NoNewPrivs: 1"), "{status}");
+        if report.syscalls == Protection::Active {
+            assert!(status.contains("Seccomp: 2"), "{status}");
+        }
+    }
+
+    #[test]
+    fn test_disabled_sandbox() {
+        let mut command = Command::new("true");
+        let pending = apply_sandbox(
+            &mut command,
+            &Default::default(),
+            Path::new("/"),
+            Path::new("/"),
+        )
+        .unwrap();
+        let report = pending.read().unwrap();
+        assert_eq!(report, SandboxReport::default());
+        assert_eq!(
+            report.to_string(),
+            "filesystem disabled, syscalls disabled, capabilities disabled"
+        );
+    }
+}
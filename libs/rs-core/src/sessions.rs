@@ -0,0 +1,149 @@
+//! Concurrent sessions of several networks.
+//!
+//! Each network connected at once runs its own walletshield with its own
+//! listen address and status, addressed by network ID. The configured listen
+//! address goes to the first session; later sessions fall back to a free port
+//! unless given an address of their own.
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    client::{ClientStatus, NetworkClient},
+    context::AppContext,
+    supervisor::Shutdown,
+};
+
+pub struct NetworkSessions {
+    ctx: AppContext,
+    clients: Mutex<BTreeMap<String, NetworkClient>>,
+}
+
+impl NetworkSessions {
+    pub fn new(ctx: AppContext) -> Self {
+        Self {
+            ctx,
+            clients: Mutex::default(),
+        }
+    }
+
+    /// The client of a network, ready to be started.
+    ///
+    /// A running client is returned as is; otherwise a new client listening
+    /// on `listen_address` (or the configured address) replaces any previous
+    /// one, so that its events can be subscribed to before it starts.
+    pub fn client(&self, network_id: &str, listen_address: Option<&str>) -> Result<NetworkClient> {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(network_id).filter(|c| c.is_running()) {
+            return Ok(client.clone());
+        }
+
+        let mut ctx = self.ctx.clone();
+        match listen_address {
+            Some(addr) => ctx.config.walletshield_listen_address = addr.to_owned(),
+            None if clients.values().any(NetworkClient::is_running) => {
+                ctx.config.listen.fallback = true;
+            }
+            None => {}
+        }
+        let client = NetworkClient::new(ctx, network_id)?;
+        clients.insert(network_id.to_owned(), client.clone());
+        Ok(client)
+    }
+
+    pub fn context(&self) -> &AppContext {
+        &self.ctx
+    }
+
+    /// Start a session of a network.
+    pub async fn start(
+        &self,
+        network_id: &str,
+        listen_address: Option<&str>,
+    ) -> Result<NetworkClient> {
+        let client = self.client(network_id, listen_address)?;
+        client.start().await?;
+        Ok(client)
+    }
+
+    /// The client of a network, if it was started before.
+    pub fn get(&self, network_id: &str) -> Option<NetworkClient> {
+        self.clients.lock().unwrap().get(network_id).cloned()
+    }
+
+    pub async fn stop(&self, network_id: &str) -> Result<Shutdown> {
+        let client = self
+            .get(network_id)
+            .with_context(|| format!("network {network_id} is not connected"))?;
+        client.stop().await
+    }
+
+    /// Stop every running session, returning how each ended.
+    pub async fn stop_all(&self) -> Vec<(String, Result<Shutdown>)> {
+        let clients: Vec<_> = self
+            .running()
+            .into_iter()
+            .filter_map(|id| self.get(&id))
+            .collect();
+        let stops = clients
+            .iter()
+            .map(|c| async move { (c.network_id().to_owned(), c.stop().await) });
+        futures_util::future::join_all(stops).await
+    }
+
+    /// IDs of the networks whose client is running.
+    pub fn running(&self) -> Vec<String> {
+        let clients = self.clients.lock().unwrap();
+        clients
+            .iter()
+            .filter(|(_, c)| c.is_running())
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Status of every network started before, by network ID.
+    pub fn status(&self) -> Vec<ClientStatus> {
+        let clients = self.clients.lock().unwrap();
+        clients.values().map(NetworkClient::status).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths::AppPaths;
+
+    #[tokio::test]
+    async fn test_sessions_are_addressed_by_network() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = r#"{
+            "apiListenAddress": "127.0.0.1:0",
+            "urlNetwork": "http://127.0.0.1:9",
+            "walletshieldListenAddress": ":0"
+        }"#;
+        let ctx = AppContext::from_paths(
+            AppPaths::from_dir(tmp.path().to_path_buf()),
+            "0.1.0",
+            config,
+            "linux-x64".into(),
+        );
+        let sessions = NetworkSessions::new(ctx);
+
+        // neither network is installed, so both fail to start
+        assert!(sessions.start("net-a", None).await.is_err());
+        assert!(sessions.start("net-b", Some(":0")).await.is_err());
+        assert!(sessions.start("../etc", None).await.is_err());
+        assert!(sessions.running().is_empty());
+
+        let ids: Vec<_> = sessions
+            .status()
+            .into_iter()
+            .map(|s| s.network_id)
+            .collect();
+        assert_eq!(ids, ["net-a", "net-b"]);
+        assert!(sessions.get("net-a").is_some());
+        assert!(sessions.stop("net-c").await.is_err());
+        assert!(sessions.stop_all().await.is_empty());
+    }
+}
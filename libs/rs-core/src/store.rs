@@ -0,0 +1,313 @@
+//! Content-addressed blob store shared across networks.
+//!
+//! Assets are stored once under `blobs/<sha256>` and referenced from network
+//! directories via hard links (falling back to symlinks, then copies). The
+//! referencing paths are tracked in `refs.json` so that blobs no longer used
+//! by any network can be garbage-collected.
+//!
+//! The CLI and the GUI share the store, so changes to `refs.json` and the
+//! removal of blobs are serialized by an OS file lock on `store.lock`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Map of blob hash to the set of paths referencing it.
+type RefIndex = BTreeMap<String, BTreeSet<PathBuf>>;
+
+/// How a blob was made available at its destination path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    Hard,
+    Symbolic,
+    Copy,
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LinkKind::Hard => "hard link",
+            LinkKind::Symbolic => "symlink",
+            LinkKind::Copy => "copy",
+        })
+    }
+}
+
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Lock the store, across processes, until the returned file is closed.
+    ///
+    /// Serializes read-modify-write cycles of the reference index.
+    fn lock(&self) -> Result<File> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join("store.lock");
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        file.lock()
+            .with_context(|| format!("failed to lock {}", path.display()))?;
+        Ok(file)
+    }
+
+    pub fn blob_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("blobs").join(hash)
+    }
+
+    fn path_index(&self) -> PathBuf {
+        self.dir.join("refs.json")
+    }
+
+    /// Move `src` into the store and link the resulting blob to `dest`.
+    ///
+    /// If a blob with the same content already exists, `src` is discarded.
+    /// Any blob previously referenced by `dest` is released.
+    pub fn adopt(&self, src: &Path, dest: &Path) -> Result<(String, LinkKind)> {
+        let hash = hash_file(src)?;
+        let blob = self.blob_path(&hash);
+
+        let _lock = self.lock()?;
+
+        // an existing blob is only reused if intact, as hard links to it may
+        // have been written through
+        let intact = blob.exists() && hash_file(&blob).is_ok_and(|h| h == hash);
+        if intact {
+            fs::remove_file(src)?;
+        } else {
+            if blob.exists() {
+                fs::remove_file(&blob)?;
+            }
+            fs::create_dir_all(self.dir.join("blobs"))?;
+            if fs::rename(src, &blob).is_err() {
+                // e.g. src and store on different filesystems
+                fs::copy(src, &blob)?;
+                fs::remove_file(src)?;
+            }
+        }
+
+        let kind = link(&blob, dest)
+            .with_context(|| format!("failed to link blob {hash} to {}", dest.display()))?;
+
+        let mut index = self.load_index()?;
+        for refs in index.values_mut() {
+            refs.remove(dest);
+        }
+        index
+            .entry(hash.clone())
+            .or_default()
+            .insert(dest.to_path_buf());
+        self.save_index(&index)?;
+
+        Ok((hash, kind))
+    }
+
+    /// Drop every reference held by `dest` or by paths below it.
+    pub fn release(&self, dest: &Path) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut index = self.load_index()?;
+        for refs in index.values_mut() {
+            refs.retain(|p| !p.starts_with(dest));
+        }
+        self.save_index(&index)
+    }
+
+    /// Number of paths currently referencing the blob `hash`.
+    pub fn refcount(&self, hash: &str) -> Result<usize> {
+        let _lock = self.lock()?;
+        Ok(self.load_index()?.get(hash).map_or(0, BTreeSet::len))
+    }
+
+    /// Remove blobs that are no longer referenced, returning their hashes.
+    ///
+    /// References whose path no longer exists (e.g. a network directory was
+    /// deleted by hand) are pruned first.
+    pub fn gc(&self) -> Result<Vec<String>> {
+        let _lock = self.lock()?;
+
+        let mut index = self.load_index()?;
+        for refs in index.values_mut() {
+            refs.retain(|p| p.symlink_metadata().is_ok());
+        }
+        index.retain(|_, refs| !refs.is_empty());
+
+        let mut removed = Vec::new();
+        let dir_blobs = self.dir.join("blobs");
+        if dir_blobs.exists() {
+            for entry in fs::read_dir(&dir_blobs)? {
+                let entry = entry?;
+                let hash = entry.file_name().to_string_lossy().into_owned();
+                if !index.contains_key(&hash) {
+                    fs::remove_file(entry.path())?;
+                    removed.push(hash);
+                }
+            }
+        }
+
+        self.save_index(&index)?;
+        removed.sort();
+        Ok(removed)
+    }
+
+    fn load_index(&self) -> Result<RefIndex> {
+        match fs::read_to_string(self.path_index()) {
+            Ok(s) => Ok(serde_json::from_str(&s)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(RefIndex::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save_index(&self, index: &RefIndex) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let tmp = self.path_index().with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(index)?)?;
+        fs::rename(tmp, self.path_index())?;
+        Ok(())
+    }
+}
+
+/// Make `blob` available at `dest`, replacing whatever is there.
+fn link(blob: &Path, dest: &Path) -> io::Result<LinkKind> {
+    match fs::remove_file(dest) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    if fs::hard_link(blob, dest).is_ok() {
+        return Ok(LinkKind::Hard);
+    }
+
+    #[cfg(unix)]
+    if std::os::unix::fs::symlink(blob, dest).is_ok() {
+        return Ok(LinkKind::Symbolic);
+    }
+
+    fs::copy(blob, dest)?;
+    Ok(LinkKind::Copy)
+}
+
+/// Compute the hex-encoded SHA-256 digest of a file.
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("failed to open {} for hashing", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, data: &[u8]) {
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn test_identical_assets_share_a_blob() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = BlobStore::new(tmp.path().join("store"));
+        let (a, b) = (tmp.path().join("a"), tmp.path().join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+
+        write(&a.join("bin.part"), b"walletshield");
+        write(&b.join("bin.part"), b"walletshield");
+        let (h1, kind) = store.adopt(&a.join("bin.part"), &a.join("bin")).unwrap();
+        let (h2, _) = store.adopt(&b.join("bin.part"), &b.join("bin")).unwrap();
+
+        assert_eq!(h1, h2);
+        assert_eq!(kind, LinkKind::Hard);
+        assert_eq!(store.refcount(&h1).unwrap(), 2);
+        assert_eq!(fs::read(b.join("bin")).unwrap(), b"walletshield");
+        assert!(!a.join("bin.part").exists());
+    }
+
+    #[test]
+    fn test_modified_blob_is_replaced() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = BlobStore::new(tmp.path().join("store"));
+        let dest = tmp.path().join("bin");
+
+        write(&tmp.path().join("bin.part"), b"walletshield");
+        let (hash, _) = store.adopt(&tmp.path().join("bin.part"), &dest).unwrap();
+        // writing through the hard link modifies the blob
+        write(&dest, b"tampered");
+
+        write(&tmp.path().join("bin.part"), b"walletshield");
+        store.adopt(&tmp.path().join("bin.part"), &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"walletshield");
+        assert_eq!(hash_file(&store.blob_path(&hash)).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_gc_removes_unreferenced_blobs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = BlobStore::new(tmp.path().join("store"));
+        let dest = tmp.path().join("bin");
+
+        write(&tmp.path().join("v1"), b"v1");
+        let (h1, _) = store.adopt(&tmp.path().join("v1"), &dest).unwrap();
+        write(&tmp.path().join("v2"), b"v2");
+        let (h2, _) = store.adopt(&tmp.path().join("v2"), &dest).unwrap();
+
+        assert_eq!(store.refcount(&h1).unwrap(), 0);
+        assert_eq!(store.gc().unwrap(), vec![h1.clone()]);
+        assert!(!store.blob_path(&h1).exists());
+        assert!(store.blob_path(&h2).exists());
+
+        // references from deleted paths are pruned
+        fs::remove_file(&dest).unwrap();
+        assert_eq!(store.gc().unwrap(), vec![h2]);
+    }
+
+    #[test]
+    fn test_store_is_locked_across_handles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = BlobStore::new(tmp.path().join("store"));
+        let lock = store.lock().unwrap();
+
+        // as another process would open it
+        let other = File::open(tmp.path().join("store/store.lock")).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(lock);
+        assert!(other.try_lock().is_ok());
+    }
+
+    #[test]
+    fn test_release_drops_references_below_a_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = BlobStore::new(tmp.path().join("store"));
+        let dir = tmp.path().join("net");
+        fs::create_dir_all(&dir).unwrap();
+
+        write(&dir.join("x.part"), b"x");
+        let (hash, _) = store.adopt(&dir.join("x.part"), &dir.join("x")).unwrap();
+        store.release(&dir).unwrap();
+
+        assert_eq!(store.refcount(&hash).unwrap(), 0);
+        assert_eq!(store.gc().unwrap(), vec![hash]);
+    }
+}
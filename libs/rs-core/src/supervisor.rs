@@ -0,0 +1,280 @@
+//! Supervision of the walletshield process.
+//!
+//! Walletshield is restarted when it exits without being asked to, with an
+//! exponential backoff between quick successive exits. Too many exits within
+//! a short window are treated as a crash loop, which is reported instead of
+//! restarting forever.
+//!
+//! When stopped, walletshield is first asked to exit with `SIGTERM` and only
+//! killed once the configured grace period has passed.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    process::ExitStatus,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{process::Child, sync::watch};
+
+use crate::{
+    config::SupervisorConfig,
+    events::{Events, LifecycleEvent},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProcessState {
+    #[default]
+    Starting,
+    Running,
+    /// Waiting to restart after an unexpected exit
+    Restarting,
+    Stopped,
+    /// Gave up after a crash loop
+    Failed,
+}
+
+/// How the process ended after a stop was requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Shutdown {
+    /// Exited within the grace period (or was not running)
+    Graceful,
+    /// Killed after the grace period
+    Forced,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SupervisorStatus {
+    pub state: ProcessState,
+    pub pid: Option<u32>,
+    /// Number of restarts after unexpected exits
+    pub restarts: u32,
+    /// Exit status of the last process, e.g. `exit status: 1`
+    pub last_exit: Option<String>,
+}
+
+pub struct Supervisor {
+    cfg: SupervisorConfig,
+    status: Mutex<SupervisorStatus>,
+    stop: watch::Sender<bool>,
+    events: Option<Events>,
+}
+
+impl Supervisor {
+    pub fn new(cfg: SupervisorConfig) -> Self {
+        Self {
+            cfg,
+            status: Mutex::default(),
+            stop: watch::Sender::new(false),
+            events: None,
+        }
+    }
+
+    /// Emit an `Exited` event whenever the process exits.
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn status(&self) -> SupervisorStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Ask `run` to stop the process and return.
+    pub fn stop(&self) {
+        self.stop.send_replace(true);
+    }
+
+    /// Run processes created by `spawn` until stopped, restarting them when
+    /// they exit.
+    ///
+    /// Fails if `spawn` fails or the process keeps crashing.
+    pub async fn run<F, Fut>(&self, mut spawn: F) -> Result<Shutdown>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<Child>>,
+    {
+        let mut stop = self.stop.subscribe();
+        let window = Duration::from_secs(self.cfg.crash_loop_window_secs);
+        let backoff_max = Duration::from_millis(self.cfg.backoff_max_ms);
+        let mut backoff = Duration::from_millis(self.cfg.backoff_initial_ms);
+        let mut exits = VecDeque::new();
+        let mut shutdown = Shutdown::Graceful;
+
+        loop {
+            if *stop.borrow() {
+                break;
+            }
+
+            let mut child = spawn().await?;
+            let started = Instant::now();
+            self.update(|s| {
+                s.state = ProcessState::Running;
+                s.pid = child.id();
+            });
+
+            let status = tokio::select! {
+                status = child.wait() => status?,
+                _ = stopped(&mut stop) => {
+                    shutdown = self.terminate(&mut child).await?;
+                    break;
+                }
+            };
+            self.record_exit(status);
+
+            // a process that ran for a while starts over with a short backoff
+            let now = Instant::now();
+            if now - started >= window {
+                backoff = Duration::from_millis(self.cfg.backoff_initial_ms);
+            }
+            exits.push_back(now);
+            while exits.front().is_some_and(|t| now - *t > window) {
+                exits.pop_front();
+            }
+            if exits.len() >= self.cfg.crash_loop_exits {
+                self.update(|s| s.state = ProcessState::Failed);
+                bail!(
+                    "walletshield exited {} times within {}s (last: {status}); giving up",
+                    exits.len(),
+                    window.as_secs()
+                );
+            }
+
+            println!("Walletshield exited with {status}; restarting in {backoff:?}");
+            self.update(|s| s.state = ProcessState::Restarting);
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = stopped(&mut stop) => break,
+            }
+            backoff = (backoff * 2).min(backoff_max);
+            self.update(|s| s.restarts += 1);
+        }
+
+        self.update(|s| {
+            s.state = ProcessState::Stopped;
+            s.pid = None;
+        });
+        Ok(shutdown)
+    }
+
+    /// Ask the process to exit, killing it if it does not within the grace
+    /// period.
+    async fn terminate(&self, child: &mut Child) -> Result<Shutdown> {
+        #[cfg(unix)]
+        if let Some(pid) = child.id() {
+            // SAFETY: kill has no memory-safety preconditions, and the pid
+            // belongs to our child, which has not been reaped yet
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+            let grace = Duration::from_millis(self.cfg.shutdown_grace_ms);
+            if let Ok(status) = tokio::time::timeout(grace, child.wait()).await {
+                self.record_exit(status?);
+                return Ok(Shutdown::Graceful);
+            }
+            println!("Walletshield did not exit within {grace:?}; killing it");
+        }
+
+        child.kill().await?;
+        if let Some(status) = child.try_wait()? {
+            self.record_exit(status);
+        }
+        Ok(Shutdown::Forced)
+    }
+
+    fn record_exit(&self, status: ExitStatus) {
+        self.update(|s| {
+            s.pid = None;
+            s.last_exit = Some(status.to_string());
+        });
+        if let Some(events) = &self.events {
+            events.emit(LifecycleEvent::Exited {
+                status: status.to_string(),
+            });
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut SupervisorStatus)) {
+        f(&mut self.status.lock().unwrap());
+    }
+}
+
+/// Wait until a stop is requested.
+async fn stopped(stop: &mut watch::Receiver<bool>) {
+    // the returned borrow of the value must not be held across awaits
+    let _ = stop.wait_for(|stop| *stop).await;
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn cfg() -> SupervisorConfig {
+        SupervisorConfig {
+            backoff_initial_ms: 1,
+            backoff_max_ms: 10,
+            crash_loop_exits: 3,
+            crash_loop_window_secs: 60,
+            shutdown_grace_ms: 100,
+        }
+    }
+
+    fn sh(script: &str) -> Result<Child> {
+        Ok(tokio::process::Command::new("sh")
+            .args(["-c", script])
+            .kill_on_drop(true)
+            .spawn()?)
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_on_crash_loop() {
+        let supervisor = Supervisor::new(cfg());
+        let err = supervisor.run(|| async { sh("exit 3") }).await.unwrap_err();
+        assert!(err.to_string().contains("3 times"), "{err}");
+
+        let status = supervisor.status();
+        assert_eq!(status.state, ProcessState::Failed);
+        assert_eq!(status.restarts, 2);
+        assert_eq!(status.last_exit.as_deref(), Some("exit status: 3"));
+    }
+
+    /// Run `script` under a supervisor and stop it once running.
+    async fn run_and_stop(script: &'static str) -> (Shutdown, SupervisorStatus) {
+        let supervisor = Arc::new(Supervisor::new(cfg()));
+        let run = tokio::spawn({
+            let supervisor = supervisor.clone();
+            async move { supervisor.run(|| async { sh(script) }).await }
+        });
+
+        while supervisor.status().state != ProcessState::Running {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // give the shell time to install its traps
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        supervisor.stop();
+        let shutdown = run.await.unwrap().unwrap();
+        (shutdown, supervisor.status())
+    }
+
+    #[tokio::test]
+    async fn test_stop_terminates_the_process() {
+        let (shutdown, status) = run_and_stop("sleep 60").await;
+        assert_eq!(shutdown, Shutdown::Graceful);
+        assert_eq!(status.state, ProcessState::Stopped);
+        assert_eq!(status.restarts, 0);
+        assert_eq!(status.last_exit.as_deref(), Some("signal: 15 (SIGTERM)"));
+    }
+
+    #[tokio::test]
+    async fn test_stop_kills_a_process_ignoring_sigterm() {
+        let (shutdown, status) = run_and_stop("trap '' TERM; while :; do sleep 1; done").await;
+        assert_eq!(shutdown, Shutdown::Forced);
+        assert_eq!(status.state, ProcessState::Stopped);
+    }
+}
@@ -0,0 +1,158 @@
+//! Detection of new versions of installed network assets.
+//!
+//! The version of each downloaded asset (`ETag`, `Last-Modified` and
+//! `Content-Length`) is recorded at install time and later compared with the
+//! server's answer to a `HEAD` request.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Result;
+use futures_util::future::try_join_all;
+use reqwest::{
+    header::{HeaderMap, CONTENT_LENGTH, ETAG, LAST_MODIFIED},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{paths::AppPaths, registry::NetworkRegistry};
+
+/// Version of an asset as served by the network.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteAsset {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_length: Option<u64>,
+}
+
+impl RemoteAsset {
+    pub fn from_headers(url: &str, headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        Self {
+            url: url.to_owned(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            content_length: header(CONTENT_LENGTH).and_then(|v| v.parse().ok()),
+        }
+    }
+
+    /// Whether `other` is a different version, judged by the most specific
+    /// validator both sides know.
+    pub fn differs(&self, other: &RemoteAsset) -> bool {
+        if let (Some(a), Some(b)) = (&self.etag, &other.etag) {
+            return a != b;
+        }
+        if let (Some(a), Some(b)) = (&self.last_modified, &other.last_modified) {
+            return a != b;
+        }
+        if let (Some(a), Some(b)) = (self.content_length, other.content_length) {
+            return a != b;
+        }
+        false
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetUpdate {
+    pub name: String,
+    pub installed: RemoteAsset,
+    pub available: RemoteAsset,
+}
+
+/// Check an installed network's assets against the server.
+///
+/// The outdated assets are also recorded as pending updates in the registry.
+pub async fn check_updates(paths: &AppPaths, network_id: &str) -> Result<Vec<AssetUpdate>> {
+    let registry = NetworkRegistry::new(paths);
+    let meta = registry.load(network_id)?;
+
+    let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+    let checks = meta.remote.iter().map(|(name, installed)| {
+        let client = &client;
+        async move {
+            let resp = client
+                .head(&installed.url)
+                .send()
+                .await?
+                .error_for_status()?;
+            let available = RemoteAsset::from_headers(&installed.url, resp.headers());
+            Ok::<_, anyhow::Error>(installed.differs(&available).then(|| AssetUpdate {
+                name: name.clone(),
+                installed: installed.clone(),
+                available,
+            }))
+        }
+    });
+    let updates: Vec<AssetUpdate> = try_join_all(checks).await?.into_iter().flatten().collect();
+
+    // re-load so that concurrent changes to the metadata are not lost
+    let mut meta = registry.load(network_id)?;
+    meta.pending_updates = updates.iter().map(|u| u.name.clone()).collect();
+    registry.save(&meta)?;
+
+    Ok(updates)
+}
+
+/// Check all installed networks, returning those with outdated assets.
+///
+/// Networks that cannot be checked (e.g. while offline) are skipped.
+pub async fn check_all_updates(paths: &AppPaths) -> Result<BTreeMap<String, Vec<AssetUpdate>>> {
+    let mut outdated = BTreeMap::new();
+    for n in NetworkRegistry::new(paths).list()? {
+        match check_updates(paths, &n.meta.id).await {
+            Ok(updates) if updates.is_empty() => {}
+            Ok(updates) => {
+                outdated.insert(n.meta.id, updates);
+            }
+            Err(e) => eprintln!("Failed to check network {} for updates: {e:#}", n.meta.id),
+        }
+    }
+    Ok(outdated)
+}
+
+/// Check all installed networks every `interval`, passing any outdated ones
+/// to `on_updates`. Runs forever.
+pub async fn watch_updates<F>(paths: AppPaths, interval: Duration, mut on_updates: F)
+where
+    F: FnMut(BTreeMap<String, Vec<AssetUpdate>>) + Send,
+{
+    loop {
+        match check_all_updates(&paths).await {
+            Ok(outdated) if !outdated.is_empty() => on_updates(outdated),
+            Ok(_) => {}
+            Err(e) => eprintln!("Failed to check for updates: {e:#}"),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(etag: Option<&str>, last_modified: Option<&str>, len: Option<u64>) -> RemoteAsset {
+        RemoteAsset {
+            url: String::new(),
+            etag: etag.map(Into::into),
+            last_modified: last_modified.map(Into::into),
+            content_length: len,
+        }
+    }
+
+    #[test]
+    fn test_differs_prefers_the_most_specific_validator() {
+        let installed = remote(Some("\"a\""), Some("Mon"), Some(1));
+        assert!(!installed.differs(&remote(Some("\"a\""), Some("Tue"), Some(2))));
+        assert!(installed.differs(&remote(Some("\"b\""), Some("Mon"), Some(1))));
+        assert!(installed.differs(&remote(None, Some("Tue"), Some(1))));
+        assert!(installed.differs(&remote(None, None, Some(2))));
+        assert!(!installed.differs(&remote(None, None, None)));
+    }
+}
@@ -1,4 +1,7 @@
-use std::env;
+use std::{
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 fn map_platform_arch(os: &str, arch: &str) -> Result<String, String> {
     match (os, arch) {
@@ -15,6 +18,13 @@ pub fn get_platform_arch() -> Result<String, String> {
     map_platform_arch(env::consts::OS, env::consts::ARCH)
 }
 
+/// Current time as a Unix timestamp in seconds
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,211 @@
+//! Installs from a mock server, with binaries stubbed as ELF files and thus
+//! run on Linux only.
+#![cfg(target_os = "linux")]
+
+use std::{collections::HashMap, convert::Infallible, fs, net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use http_body_util::Full;
+use hyper::{server::conn::http1, service::service_fn, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+
+use zknet_core::{
+    context::AppContext,
+    events::{Events, LifecycleEvent},
+    network_install,
+    paths::AppPaths,
+    registry::NetworkRegistry,
+    utils::get_platform_arch,
+};
+
+type Files = Arc<HashMap<String, Vec<u8>>>;
+
+/// Serve the static `files` by request path.
+async fn handle_req(
+    req: Request<hyper::body::Incoming>,
+    files: Files,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    Ok(match files.get(req.uri().path()) {
+        Some(data) => Response::new(Full::from(Bytes::from(data.clone()))),
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::from(Bytes::new()))
+            .unwrap(),
+    })
+}
+
+/// Spawn an HTTP/1.1 server on an ephemeral port.
+async fn spawn_test_server(files: Files) -> Result<SocketAddr> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let files = files.clone();
+            tokio::spawn(async move {
+                let svc = service_fn(move |req| handle_req(req, files.clone()));
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), svc)
+                    .await;
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+/// A minimal ELF header for the current architecture.
+fn elf_stub(tag: &[u8]) -> Vec<u8> {
+    let mut bin = vec![0; 64];
+    bin[..6].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1]);
+    let machine: u16 = if std::env::consts::ARCH == "aarch64" {
+        0xb7
+    } else {
+        0x3e
+    };
+    bin[18..20].copy_from_slice(&machine.to_le_bytes());
+    bin.extend_from_slice(tag);
+    bin
+}
+
+/// A `.tar.gz` holding the walletshield binary and `extra` files.
+fn walletshield_archive(bin: &[u8], extra: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+    let extra = extra.iter().map(|&(name, data)| (name, data, 0o644));
+    for (name, data, mode) in [("walletshield", bin, 0o755)].into_iter().chain(extra) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(mode);
+        header.set_cksum();
+        tar.append_data(&mut header, name, data).unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap()
+}
+
+fn context(dir: &std::path::Path, addr: SocketAddr) -> AppContext {
+    let config_json = format!(
+        r#"{{
+            "apiListenAddress": "127.0.0.1:0",
+            "urlNetwork": "http://{addr}",
+            "walletshieldListenAddress": ":0"
+        }}"#
+    );
+    let platform_arch = get_platform_arch().unwrap();
+    AppContext::from_paths(
+        AppPaths::from_dir(dir.into()),
+        "0.1.0",
+        &config_json,
+        platform_arch,
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn install_shares_binaries_between_networks() -> Result<()> {
+    let platform_arch = get_platform_arch().unwrap();
+    let bin = elf_stub(b"walletshield");
+
+    let mut files = HashMap::new();
+    for net in ["net-a", "net-b", "net-c"] {
+        files.insert(
+            format!("/{net}/client.toml"),
+            format!("# {net}").into_bytes(),
+        );
+        files.insert(format!("/{net}/services.json"), b"{}".to_vec());
+    }
+    // an error page served in place of the binary is refused
+    files.insert("/net-d/client.toml".into(), b"".to_vec());
+    files.insert("/net-d/services.json".into(), b"{}".to_vec());
+    files.insert(
+        format!("/net-d/walletshield-{platform_arch}"),
+        b"<html>Not Found</html>".to_vec(),
+    );
+    files.insert(format!("/net-a/walletshield-{platform_arch}"), bin.clone());
+    files.insert(format!("/net-b/walletshield-{platform_arch}"), bin.clone());
+    files.insert(
+        format!("/net-c/walletshield-{platform_arch}"),
+        walletshield_archive(&bin, &[("LICENSE", b"MIT")]),
+    );
+    // archives must not replace files written by the client or other assets
+    for (net, name) in [("net-e", "meta.json"), ("net-f", "services.json")] {
+        files.insert(format!("/{net}/client.toml"), b"".to_vec());
+        files.insert(format!("/{net}/services.json"), b"{}".to_vec());
+        files.insert(
+            format!("/{net}/walletshield-{platform_arch}"),
+            walletshield_archive(&bin, &[(name, b"{}")]),
+        );
+    }
+    // a network requiring a newer client is not installed
+    files.insert(
+        "/networks.json".into(),
+        br#"{"networks": [{"id": "net-g", "name": "G", "minClientVersion": "99.0.0"}]}"#.to_vec(),
+    );
+    files.insert("/net-g/client.toml".into(), b"".to_vec());
+    files.insert("/net-g/services.json".into(), b"{}".to_vec());
+    files.insert(format!("/net-g/walletshield-{platform_arch}"), bin.clone());
+    let addr = spawn_test_server(Arc::new(files)).await?;
+
+    let tmp = tempfile::tempdir()?;
+    let ctx = context(tmp.path(), addr);
+    for net in ["net-a", "net-b", "net-c"] {
+        let events = Events::new(net);
+        let mut rx = events.subscribe();
+        network_install(&ctx, net, &events).await?;
+
+        // every asset reports its progress before the install is recorded
+        let mut kinds = Vec::new();
+        while let Ok(e) = rx.try_recv() {
+            kinds.push(e.event);
+        }
+        for asset in ["client.toml", "services.json", "walletshield"] {
+            assert!(kinds.iter().any(|k| matches!(
+                k,
+                LifecycleEvent::Downloading { asset: a, .. } if a == asset
+            )));
+        }
+        assert!(kinds.contains(&LifecycleEvent::Verifying));
+        assert_eq!(kinds.last(), Some(&LifecycleEvent::Installing));
+    }
+
+    let registry = NetworkRegistry::new(&ctx.paths);
+    let a = registry.load("net-a")?;
+    let c = registry.load("net-c")?;
+    let bin_a = a.assets.iter().find(|r| r.name.starts_with("walletshield"));
+    let bin_c = c.assets.iter().find(|r| r.name.starts_with("walletshield"));
+    assert_eq!(bin_a.unwrap().sha256, bin_c.unwrap().sha256);
+    assert_eq!(registry.store().refcount(&bin_a.unwrap().sha256)?, 3);
+
+    // the archive's extra files are installed alongside the binary
+    let dir_c = registry.dir_network("net-c");
+    assert_eq!(fs::read(dir_c.join("LICENSE"))?, b"MIT");
+    assert!(c.assets.iter().any(|r| r.name == "LICENSE"));
+    assert!(!dir_c.join("walletshield.part").exists());
+
+    // an error page served in place of the binary is refused
+    let err = network_install(&ctx, "net-d", &Events::new("net-d"))
+        .await
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("HTML"), "{err:#}");
+
+    for net in ["net-e", "net-f"] {
+        let err = network_install(&ctx, net, &Events::new(net))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains(".json"), "{err:#}");
+        assert!(registry.load(net)?.assets.is_empty());
+    }
+
+    let err = network_install(&ctx, "net-g", &Events::new("net-g"))
+        .await
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("upgrade"), "{err:#}");
+    assert!(!registry.dir_network("net-g").exists());
+
+    // a missing asset fails the install
+    assert!(network_install(&ctx, "missing", &Events::new("missing"))
+        .await
+        .is_err());
+
+    Ok(())
+}